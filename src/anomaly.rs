@@ -0,0 +1,153 @@
+// Learns what normal readings look like for every hour of the day and
+// scores new readings by how many standard deviations they are away from
+// the mean (z-score). Places with a lot of normal movement learn a wide
+// distribution and only unusual readings stand out.
+
+pub const BUCKETS: usize = 24;
+// mean, variance and count of every bucket
+pub const STORED_SIZE: usize = BUCKETS * BUCKET_SIZE;
+
+const BUCKET_SIZE: usize = 8 + 8 + 4;
+
+// readings needed before a bucket is trusted
+const MIN_SAMPLES: u32 = 50;
+// sensor noise, stops a perfectly still room from flagging every millimeter
+const MIN_DEVIATION: f64 = 10.0;
+
+#[derive(Clone, Copy)]
+struct Bucket {
+    mean: f64,
+    variance: f64,
+    count: u32,
+}
+
+pub struct AnomalyDetector {
+    // weight of a new reading, smaller values learn slower
+    alpha: f64,
+    threshold: f64,
+    buckets: [Bucket; BUCKETS],
+}
+
+impl AnomalyDetector {
+    pub fn new(alpha: f64, threshold: f64) -> Self {
+        Self {
+            alpha,
+            threshold,
+            buckets: [Bucket {
+                mean: 0.0,
+                variance: 0.0,
+                count: 0,
+            }; BUCKETS],
+        }
+    }
+
+    // restores buckets saved with `to_bytes`, `alpha` and `threshold` come from the config
+    pub fn from_bytes(alpha: f64, threshold: f64, bytes: &[u8; STORED_SIZE]) -> Self {
+        let mut detector = Self::new(alpha, threshold);
+        for (bucket, bytes) in detector
+            .buckets
+            .iter_mut()
+            .zip(bytes.chunks_exact(BUCKET_SIZE))
+        {
+            let mut mean = [0u8; 8];
+            let mut variance = [0u8; 8];
+            let mut count = [0u8; 4];
+            mean.copy_from_slice(&bytes[..8]);
+            variance.copy_from_slice(&bytes[8..16]);
+            count.copy_from_slice(&bytes[16..]);
+            *bucket = Bucket {
+                mean: f64::from_le_bytes(mean),
+                variance: f64::from_le_bytes(variance),
+                count: u32::from_le_bytes(count),
+            };
+        }
+        detector
+    }
+
+    pub fn to_bytes(&self) -> [u8; STORED_SIZE] {
+        let mut bytes = [0u8; STORED_SIZE];
+        for (bucket, bytes) in self.buckets.iter().zip(bytes.chunks_exact_mut(BUCKET_SIZE)) {
+            bytes[..8].copy_from_slice(&bucket.mean.to_le_bytes());
+            bytes[8..16].copy_from_slice(&bucket.variance.to_le_bytes());
+            bytes[16..].copy_from_slice(&bucket.count.to_le_bytes());
+        }
+        bytes
+    }
+
+    // forgets everything learned so far
+    pub fn reset(&mut self) {
+        for bucket in self.buckets.iter_mut() {
+            bucket.mean = 0.0;
+            bucket.variance = 0.0;
+            bucket.count = 0;
+        }
+    }
+
+    // scores the reading against the bucket of `hour` before learning from it.
+    // returns the z-score when it is above the threshold.
+    pub fn update(&mut self, hour: usize, millimeters: f64) -> Option<f64> {
+        let bucket = &mut self.buckets[hour % BUCKETS];
+
+        let score = if bucket.count >= MIN_SAMPLES {
+            let deviation = libm::sqrt(bucket.variance).max(MIN_DEVIATION);
+            Some(libm::fabs(millimeters - bucket.mean) / deviation)
+        } else {
+            None
+        };
+
+        // a plain average until there are enough readings, otherwise
+        // the first few readings would dominate the mean for a long time
+        let alpha = if bucket.count == 0 {
+            1.0
+        } else {
+            self.alpha.max(1.0 / (bucket.count as f64 + 1.0))
+        };
+        let diff = millimeters - bucket.mean;
+        let increment = alpha * diff;
+        bucket.mean += increment;
+        bucket.variance = (1.0 - alpha) * (bucket.variance + diff * increment);
+        bucket.count = bucket.count.saturating_add(1);
+
+        score.filter(|&score| score > self.threshold)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // alternates around 1000mm with a standard deviation of 20mm
+    fn learn(detector: &mut AnomalyDetector, hour: usize) {
+        for i in 0..MIN_SAMPLES * 2 {
+            let mm = if i % 2 == 0 { 980.0 } else { 1020.0 };
+            assert_eq!(detector.update(hour, mm), None);
+        }
+    }
+
+    #[test]
+    fn scores_only_readings_far_from_the_learned_hour() {
+        let mut detector = AnomalyDetector::new(0.01, 4.0);
+        learn(&mut detector, 3);
+        assert_eq!(detector.update(3, 1030.0), None);
+        assert!(detector.update(3, 500.0).is_some());
+        // other hours have not learned anything yet
+        assert_eq!(detector.update(4, 500.0), None);
+    }
+
+    #[test]
+    fn restores_what_it_learned() {
+        let mut detector = AnomalyDetector::new(0.01, 4.0);
+        learn(&mut detector, 22);
+        let mut restored = AnomalyDetector::from_bytes(0.01, 4.0, &detector.to_bytes());
+        assert!(restored.update(22, 500.0).is_some());
+        assert_eq!(restored.update(21, 500.0), None);
+    }
+
+    #[test]
+    fn reset_forgets_every_hour() {
+        let mut detector = AnomalyDetector::new(0.01, 4.0);
+        learn(&mut detector, 0);
+        detector.reset();
+        assert_eq!(detector.update(0, 500.0), None);
+    }
+}
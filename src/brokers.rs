@@ -0,0 +1,129 @@
+use core::fmt::Error;
+
+use heapless::Vec;
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+pub const MAX_BROKERS: usize = 4;
+
+// Brokers are written as `<host>[:<port>[:<username>:<password>]]`
+// and separated by commas, in the order they should be tried.
+//
+//   192.168.1.10:1883:pico:secret,backup.local
+//
+// the password is the rest of the entry and may contain colons.
+
+#[derive(Clone, Copy)]
+pub struct Broker<'a> {
+    pub host: &'a str,
+    pub port: u16,
+    pub username: &'a str,
+    pub password: &'a str,
+}
+
+// sticks to the broker that works and falls back to the next one
+pub struct Brokers<'a> {
+    brokers: Vec<Broker<'a>, MAX_BROKERS>,
+    current: usize,
+    // brokers that failed in a row
+    failed: usize,
+}
+
+impl<'a> Brokers<'a> {
+    pub fn single(host: &'a str, port: u16, username: &'a str, password: &'a str) -> Result<Self> {
+        Self::new(&[Broker {
+            host,
+            port,
+            username,
+            password,
+        }])
+    }
+
+    pub fn parse(s: &'a str, default_port: u16) -> Result<Self> {
+        let mut brokers: Vec<Broker<'a>, MAX_BROKERS> = Vec::new();
+        for entry in s.split(',').filter(|b| !b.trim().is_empty()) {
+            let mut parts = entry.trim().splitn(4, ':');
+            let host = parts.next().ok_or(Error)?;
+            let port = match parts.next() {
+                Some(port) => port.parse().map_err(|_| Error)?,
+                None => default_port,
+            };
+            let username = parts.next().unwrap_or_default();
+            let password = parts.next().unwrap_or_default();
+            brokers
+                .push(Broker {
+                    host,
+                    port,
+                    username,
+                    password,
+                })
+                .map_err(|_| Error)?;
+        }
+        Self::new(&brokers)
+    }
+
+    fn new(brokers: &[Broker<'a>]) -> Result<Self> {
+        if brokers.is_empty() || brokers.iter().any(|broker| broker.host.is_empty()) {
+            return Err(Error);
+        }
+
+        Ok(Self {
+            brokers: Vec::from_slice(brokers).map_err(|_| Error)?,
+            current: 0,
+            failed: 0,
+        })
+    }
+
+    pub fn current(&self) -> &Broker<'a> {
+        &self.brokers[self.current]
+    }
+
+    pub fn connected(&mut self) {
+        self.failed = 0;
+    }
+
+    // moves on to the next broker, returns true once all of them have failed
+    pub fn fail(&mut self) -> bool {
+        self.current = (self.current + 1) % self.brokers.len();
+        self.failed += 1;
+        self.failed.is_multiple_of(self.brokers.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_hosts_ports_and_credentials() {
+        let brokers =
+            Brokers::parse("192.168.1.10:1884:pico:se:cret, backup.local,", 1883).unwrap();
+        let first = brokers.current();
+        assert_eq!(
+            (first.host, first.port, first.username, first.password),
+            ("192.168.1.10", 1884, "pico", "se:cret")
+        );
+        assert_eq!(brokers.brokers[1].host, "backup.local");
+        assert_eq!(brokers.brokers[1].port, 1883);
+        assert_eq!(brokers.brokers.len(), 2);
+    }
+
+    #[test]
+    fn rejects_invalid_lists() {
+        assert!(Brokers::parse("", 1883).is_err());
+        assert!(Brokers::parse(":1883", 1883).is_err());
+        assert!(Brokers::parse("broker:port", 1883).is_err());
+        assert!(Brokers::parse("a,b,c,d,e", 1883).is_err());
+    }
+
+    #[test]
+    fn fails_over_in_order() {
+        let mut brokers = Brokers::parse("a,b", 1883).unwrap();
+        assert!(!brokers.fail());
+        assert_eq!(brokers.current().host, "b");
+        assert!(brokers.fail());
+        assert_eq!(brokers.current().host, "a");
+        brokers.connected();
+        assert!(!brokers.fail());
+    }
+}
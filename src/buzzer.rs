@@ -0,0 +1,86 @@
+use embassy_futures::select::{select, Either};
+use embassy_rp::peripherals::{PIN_16, PWM_CH0};
+use embassy_rp::pwm::{Config, Pwm};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Timer};
+use fixed::types::U12F4;
+
+use crate::siren::{self, Command, Pattern, Sounding};
+
+// 125MHz system clock divided down to 7.8125MHz,
+// leaves enough room in the 16 bit counter for audible tones
+const CLOCK_HZ: u32 = 125_000_000;
+const DIVIDER: u32 = 16;
+
+// the siren runs in its own task so it keeps sounding
+// while the main loop is stuck on the network
+pub static SIREN: Signal<CriticalSectionRawMutex, Command> = Signal::new();
+
+// a piezo buzzer on GP16
+pub struct Buzzer {
+    pwm: Pwm<'static, PWM_CH0>,
+    config: Config,
+    pattern: Pattern,
+    low_hz: u32,
+    high_hz: u32,
+    sounding: Sounding,
+}
+
+impl Buzzer {
+    pub fn new(
+        slice: PWM_CH0,
+        pin: PIN_16,
+        pattern: Pattern,
+        low_hz: u32,
+        high_hz: u32,
+        max_duration: Duration,
+    ) -> Self {
+        let mut config = Config::default();
+        config.divider = U12F4::from_num(DIVIDER);
+        config.compare_a = 0;
+        let pwm = Pwm::new_output_a(slice, pin, config.clone());
+
+        Self {
+            pwm,
+            config,
+            pattern,
+            low_hz,
+            high_hz,
+            sounding: Sounding::new(max_duration),
+        }
+    }
+
+    pub async fn run(&mut self) -> ! {
+        let mut step: usize = 0;
+        loop {
+            if !self.sounding.is_sounding(Instant::now()) {
+                self.tone(0);
+                let command = SIREN.wait().await;
+                self.sounding.command(Instant::now(), command);
+                step = 0;
+                continue;
+            }
+
+            let (frequency, duration) = self.pattern.step(step, self.low_hz, self.high_hz);
+            self.tone(frequency);
+            step = step.wrapping_add(1);
+            // a command cuts the step short
+            if let Either::Second(command) = select(Timer::after(duration), SIREN.wait()).await {
+                self.sounding.command(Instant::now(), command);
+            }
+        }
+    }
+
+    // a square wave at `frequency`, 0 is silent
+    fn tone(&mut self, frequency: u32) {
+        match siren::pwm_top(CLOCK_HZ / DIVIDER, frequency) {
+            Some(top) => {
+                self.config.top = top;
+                self.config.compare_a = top / 2;
+            }
+            None => self.config.compare_a = 0,
+        }
+        self.pwm.set_config(&self.config);
+    }
+}
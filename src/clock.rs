@@ -0,0 +1,68 @@
+use embassy_time::Instant;
+
+pub const NTP_PACKET_SIZE: usize = 48;
+// seconds between 1900, where ntp starts counting, and 1970
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+// The pico has no battery backed clock. The wall clock time is
+// queried over sntp and then kept relative to the uptime.
+#[derive(Clone, Copy, Default)]
+pub struct Clock {
+    // unix time in milliseconds at boot
+    boot_ms: Option<u64>,
+}
+
+impl Clock {
+    pub const fn new() -> Self {
+        Self { boot_ms: None }
+    }
+
+    // a clock that reads `unix_ms` at `now`
+    pub fn synced(unix_ms: u64, now: Instant) -> Self {
+        Self {
+            boot_ms: Some(unix_ms.saturating_sub(now.as_millis())),
+        }
+    }
+
+    // None until the clock has been synced
+    pub fn unix_ms(&self, now: Instant) -> Option<u64> {
+        self.boot_ms.map(|boot_ms| boot_ms + now.as_millis())
+    }
+}
+
+// the transmit timestamp of an sntp response in unix milliseconds
+pub fn ntp_unix_ms(packet: &[u8]) -> Option<u64> {
+    let timestamp = packet.get(40..NTP_PACKET_SIZE)?;
+    // seconds and a 32 bit fraction
+    let seconds = u32::from_be_bytes(timestamp[..4].try_into().ok()?) as u64;
+    let fraction = u32::from_be_bytes(timestamp[4..].try_into().ok()?) as u64;
+    Some(seconds.checked_sub(NTP_UNIX_OFFSET)? * 1000 + ((fraction * 1000) >> 32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_time_relative_to_the_uptime() {
+        assert_eq!(Clock::new().unix_ms(Instant::from_secs(10)), None);
+        let clock = Clock::synced(1_700_000_000_000, Instant::from_secs(10));
+        assert_eq!(
+            clock.unix_ms(Instant::from_secs(70)),
+            Some(1_700_000_060_000)
+        );
+    }
+
+    #[test]
+    fn reads_the_transmit_timestamp() {
+        let mut packet = [0u8; NTP_PACKET_SIZE];
+        // 2023-11-14T22:13:20Z and a half
+        packet[40..44].copy_from_slice(&(1_700_000_000u32 + 2_208_988_800).to_be_bytes());
+        packet[44..48].copy_from_slice(&0x8000_0000u32.to_be_bytes());
+        assert_eq!(ntp_unix_ms(&packet), Some(1_700_000_000_500));
+
+        assert_eq!(ntp_unix_ms(&packet[..47]), None);
+        // before 1970, an unsynced server answers with zeros
+        assert_eq!(ntp_unix_ms(&[0; NTP_PACKET_SIZE]), None);
+    }
+}
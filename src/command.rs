@@ -0,0 +1,116 @@
+use core::fmt::Error;
+use core::str::FromStr;
+
+use heapless::String;
+use serde::Serialize;
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+// sent as plain text on `.../cmd`, every command is acknowledged on `.../ack`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Command {
+    Reboot,
+    // publish a reading right away, whatever the mode
+    MeasureNow,
+    // learn the baseline or reference again
+    Calibrate,
+    // blink the led to find the pico
+    Identify,
+    // forget everything stored in flash and reboot
+    FactoryReset,
+}
+
+impl Command {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Command::Reboot => "reboot",
+            Command::MeasureNow => "measure-now",
+            Command::Calibrate => "calibrate",
+            Command::Identify => "identify",
+            Command::FactoryReset => "factory-reset",
+        }
+    }
+}
+
+impl FromStr for Command {
+    type Err = ();
+
+    fn from_str(s: &str) -> core::result::Result<Self, Self::Err> {
+        match s.trim() {
+            "reboot" => Ok(Command::Reboot),
+            "measure-now" => Ok(Command::MeasureNow),
+            "calibrate" => Ok(Command::Calibrate),
+            "identify" => Ok(Command::Identify),
+            "factory-reset" => Ok(Command::FactoryReset),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Outcome {
+    Ok,
+    Failed,
+    // not available in the current mode
+    Unsupported,
+    Unknown,
+}
+
+impl Outcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Ok => "ok",
+            Outcome::Failed => "failed",
+            Outcome::Unsupported => "unsupported",
+            Outcome::Unknown => "unknown",
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Ack<'a> {
+    pub command: &'a str,
+    pub result: &'static str,
+}
+
+impl<'a> Ack<'a> {
+    pub fn new(command: &'a str, outcome: Outcome) -> Self {
+        Self {
+            command,
+            result: outcome.as_str(),
+        }
+    }
+
+    pub fn to_json<const N: usize>(&self) -> Result<String<N>> {
+        serde_json_core::to_string(self).map_err(|_| Error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_every_command_it_names() {
+        for command in [
+            Command::Reboot,
+            Command::MeasureNow,
+            Command::Calibrate,
+            Command::Identify,
+            Command::FactoryReset,
+        ] {
+            assert_eq!(Command::from_str(command.as_str()), Ok(command));
+        }
+        assert_eq!(Command::from_str(" reboot\n"), Ok(Command::Reboot));
+        assert_eq!(Command::from_str("shutdown"), Err(()));
+    }
+
+    #[test]
+    fn acknowledges_as_json() {
+        let ack = Ack::new("calibrate", Outcome::Unsupported);
+        assert_eq!(
+            ack.to_json::<64>().unwrap().as_str(),
+            r#"{"command":"calibrate","result":"unsupported"}"#
+        );
+    }
+}
@@ -0,0 +1,379 @@
+use core::str::FromStr;
+use core::{env, option_env};
+
+// Settings are baked into the firmware at compile time
+// through `RP_*` environment variables, see the README.
+
+pub const WIFI_NETWORK: &'static str = env!("RP_WIFI_NETWORK");
+pub const WIFI_PASSWORD: &'static str = env!("RP_WIFI_PASSWORD");
+// an ip address, hostname or `.local` name, `RP_MQTT_SERVER_IP` is still accepted
+pub const MQTT_SERVER: &'static str = match option_env!("RP_MQTT_SERVER") {
+    Some(server) => server,
+    _ => match option_env!("RP_MQTT_SERVER_IP") {
+        Some(ip) => ip,
+        _ => match option_env!("RP_MQTT_BROKERS") {
+            Some(_) => "",
+            _ => panic!("RP_MQTT_SERVER or RP_MQTT_BROKERS has to be set"),
+        },
+    },
+};
+// brokers tried in order, replaces the server, port and credentials above. see src/brokers.rs
+pub const MQTT_BROKERS: &'static str = match option_env!("RP_MQTT_BROKERS") {
+    Some(brokers) => brokers,
+    _ => "",
+};
+#[cfg(not(feature = "tls"))]
+const DEFAULT_MQTT_SERVER_PORT: &'static str = "1883";
+#[cfg(feature = "tls")]
+const DEFAULT_MQTT_SERVER_PORT: &'static str = "8883";
+pub const MQTT_SERVER_PORT: &'static str = match option_env!("RP_MQTT_SERVER_PORT") {
+    Some(port) => port,
+    _ => DEFAULT_MQTT_SERVER_PORT,
+};
+pub const MQTT_USERNAME: &'static str = match option_env!("RP_MQTT_USERNAME") {
+    Some(username) => username,
+    _ => "",
+};
+pub const MQTT_PASSWORD: &'static str = match option_env!("RP_MQTT_PASSWORD") {
+    Some(password) => password,
+    _ => "",
+};
+
+// certificates for the `tls` feature, DER encoded and read at compile time
+#[cfg(feature = "tls")]
+pub const MQTT_CA_CERT: &'static [u8] = include_bytes!(env!("RP_MQTT_CA_CERT"));
+// has to match the name in the broker certificate, defaults to the broker host
+#[cfg(feature = "tls")]
+pub const MQTT_TLS_SERVER_NAME: &'static str = match option_env!("RP_MQTT_TLS_SERVER_NAME") {
+    Some(name) => name,
+    _ => "",
+};
+#[cfg(feature = "tls-client-cert")]
+pub const MQTT_CLIENT_CERT: &'static [u8] = include_bytes!(env!("RP_MQTT_CLIENT_CERT"));
+#[cfg(feature = "tls-client-cert")]
+pub const MQTT_CLIENT_KEY: &'static [u8] = include_bytes!(env!("RP_MQTT_CLIENT_KEY"));
+
+// reconnect delays double up to this, the pico only resets
+// when the broker has been unreachable for `MQTT_MAX_OUTAGE_SECS`
+pub const MQTT_BACKOFF_MAX_SECS: &'static str = match option_env!("RP_MQTT_BACKOFF_MAX_SECS") {
+    Some(secs) => secs,
+    _ => "60",
+};
+pub const MQTT_MAX_OUTAGE_SECS: &'static str = match option_env!("RP_MQTT_MAX_OUTAGE_SECS") {
+    Some(secs) => secs,
+    _ => "900",
+};
+// measuring starts without an answer on `cmd/time` once the broker
+// hasn't sent one for this long after boot
+pub const START_TIMEOUT_SECS: &'static str = match option_env!("RP_START_TIMEOUT_SECS") {
+    Some(secs) => secs,
+    _ => "120",
+};
+
+// topics are `<prefix>/<device id>/<name>`, the device id is the mac address
+pub const TOPIC_PREFIX: &'static str = match option_env!("RP_TOPIC_PREFIX") {
+    Some(prefix) => prefix,
+    _ => "pico",
+};
+
+// publish home assistant mqtt discovery configs on connect
+pub const DISCOVERY_ENABLED: &'static str = match option_env!("RP_DISCOVERY_ENABLED") {
+    Some(enabled) => enabled,
+    _ => "true",
+};
+pub const DISCOVERY_PREFIX: &'static str = match option_env!("RP_DISCOVERY_PREFIX") {
+    Some(prefix) => prefix,
+    _ => "homeassistant",
+};
+
+// sntp server used for the wall clock time in payloads, leave empty to only send the uptime
+pub const NTP_SERVER_IP: &'static str = match option_env!("RP_NTP_SERVER_IP") {
+    Some(ip) => ip,
+    _ => "",
+};
+
+// corrects readings for sensors mounted at an angle or away from the reference point
+pub const MOUNT_OFFSET_MM: &'static str = match option_env!("RP_MOUNT_OFFSET_MM") {
+    Some(offset) => offset,
+    _ => "0",
+};
+pub const MOUNT_TILT_DEGREES: &'static str = match option_env!("RP_MOUNT_TILT_DEGREES") {
+    Some(tilt) => tilt,
+    _ => "0",
+};
+
+pub const MODE: &'static str = match option_env!("RP_MODE") {
+    Some(mode) => mode,
+    _ => "intrusion",
+};
+
+// readings closer than this for longer than `TAMPER_SECS` are reported as tampering
+pub const TAMPER_DISTANCE_MM: &'static str = match option_env!("RP_TAMPER_DISTANCE_MM") {
+    Some(distance) => distance,
+    _ => "50",
+};
+pub const TAMPER_SECS: &'static str = match option_env!("RP_TAMPER_SECS") {
+    Some(secs) => secs,
+    _ => "5",
+};
+
+// rules evaluated on every reading, see src/rules.rs
+pub const RULES: &'static str = match option_env!("RP_RULES") {
+    Some(rules) => rules,
+    _ => "",
+};
+
+// intrusion mode, `threshold` or `anomaly`
+pub const DETECTOR: &'static str = match option_env!("RP_DETECTOR") {
+    Some(detector) => detector,
+    _ => "threshold",
+};
+// weight of a new reading in the learned distribution
+pub const ANOMALY_ALPHA: &'static str = match option_env!("RP_ANOMALY_ALPHA") {
+    Some(alpha) => alpha,
+    _ => "0.01",
+};
+// z-score above which a reading is an intrusion
+pub const ANOMALY_THRESHOLD: &'static str = match option_env!("RP_ANOMALY_THRESHOLD") {
+    Some(threshold) => threshold,
+    _ => "3.0",
+};
+
+// intrusion mode, objects approaching faster than this in m/s are vehicles
+pub const VEHICLE_SPEED_MS: &'static str = match option_env!("RP_VEHICLE_SPEED_MS") {
+    Some(speed) => speed,
+    _ => "2.5",
+};
+
+// intrusion mode alarm panel
+pub const ALARM_EXIT_DELAY_SECS: &'static str = match option_env!("RP_ALARM_EXIT_DELAY_SECS") {
+    Some(delay) => delay,
+    _ => "30",
+};
+pub const ALARM_ENTRY_DELAY_SECS: &'static str = match option_env!("RP_ALARM_ENTRY_DELAY_SECS") {
+    Some(delay) => delay,
+    _ => "30",
+};
+pub const ALARM_TRIGGER_SECS: &'static str = match option_env!("RP_ALARM_TRIGGER_SECS") {
+    Some(time) => time,
+    _ => "120",
+};
+
+// piezo buzzer on GP16, sounds on an intrusion
+pub const SIREN_ENABLED: &'static str = match option_env!("RP_SIREN_ENABLED") {
+    Some(enabled) => enabled,
+    _ => "false",
+};
+// `intrusion` or `panel` to only sound while the alarm panel is triggered
+pub const SIREN_TRIGGER: &'static str = match option_env!("RP_SIREN_TRIGGER") {
+    Some(trigger) => trigger,
+    _ => "intrusion",
+};
+// `continuous`, `beep` or `siren`
+pub const SIREN_PATTERN: &'static str = match option_env!("RP_SIREN_PATTERN") {
+    Some(pattern) => pattern,
+    _ => "siren",
+};
+pub const SIREN_LOW_HZ: &'static str = match option_env!("RP_SIREN_LOW_HZ") {
+    Some(frequency) => frequency,
+    _ => "1500",
+};
+pub const SIREN_HIGH_HZ: &'static str = match option_env!("RP_SIREN_HIGH_HZ") {
+    Some(frequency) => frequency,
+    _ => "3000",
+};
+pub const SIREN_SECS: &'static str = match option_env!("RP_SIREN_SECS") {
+    Some(secs) => secs,
+    _ => "60",
+};
+
+// tank mode, all distances are in millimeters
+pub const TANK_MOUNT_HEIGHT_MM: &'static str = match option_env!("RP_TANK_MOUNT_HEIGHT_MM") {
+    Some(height) => height,
+    _ => "1000",
+};
+pub const TANK_EMPTY_DISTANCE_MM: &'static str = match option_env!("RP_TANK_EMPTY_DISTANCE_MM") {
+    Some(distance) => distance,
+    _ => "1000",
+};
+pub const TANK_FULL_DISTANCE_MM: &'static str = match option_env!("RP_TANK_FULL_DISTANCE_MM") {
+    Some(distance) => distance,
+    _ => "200",
+};
+pub const TANK_SHAPE: &'static str = match option_env!("RP_TANK_SHAPE") {
+    Some(shape) => shape,
+    _ => "vertical-cylinder",
+};
+pub const TANK_DIAMETER_MM: &'static str = match option_env!("RP_TANK_DIAMETER_MM") {
+    Some(diameter) => diameter,
+    _ => "1000",
+};
+pub const TANK_WIDTH_MM: &'static str = match option_env!("RP_TANK_WIDTH_MM") {
+    Some(width) => width,
+    _ => "1000",
+};
+pub const TANK_LENGTH_MM: &'static str = match option_env!("RP_TANK_LENGTH_MM") {
+    Some(length) => length,
+    _ => "1000",
+};
+pub const TANK_INTERVAL_SECS: &'static str = match option_env!("RP_TANK_INTERVAL_SECS") {
+    Some(interval) => interval,
+    _ => "10",
+};
+// relay on GP17 switching a pump that fills the tank
+pub const PUMP_ENABLED: &'static str = match option_env!("RP_PUMP_ENABLED") {
+    Some(enabled) => enabled,
+    _ => "false",
+};
+pub const PUMP_LOW_PERCENT: &'static str = match option_env!("RP_PUMP_LOW_PERCENT") {
+    Some(percent) => percent,
+    _ => "20",
+};
+pub const PUMP_HIGH_PERCENT: &'static str = match option_env!("RP_PUMP_HIGH_PERCENT") {
+    Some(percent) => percent,
+    _ => "90",
+};
+pub const PUMP_MIN_ON_SECS: &'static str = match option_env!("RP_PUMP_MIN_ON_SECS") {
+    Some(secs) => secs,
+    _ => "60",
+};
+pub const PUMP_MIN_OFF_SECS: &'static str = match option_env!("RP_PUMP_MIN_OFF_SECS") {
+    Some(secs) => secs,
+    _ => "300",
+};
+pub const PUMP_DRY_RUN_SECS: &'static str = match option_env!("RP_PUMP_DRY_RUN_SECS") {
+    Some(secs) => secs,
+    _ => "120",
+};
+// fill and drain rates are in litres per hour
+pub const TANK_RATE_WINDOW_SECS: &'static str = match option_env!("RP_TANK_RATE_WINDOW_SECS") {
+    Some(window) => window,
+    _ => "300",
+};
+pub const TANK_MAX_DRAIN_LPH: &'static str = match option_env!("RP_TANK_MAX_DRAIN_LPH") {
+    Some(rate) => rate,
+    _ => "5",
+};
+pub const TANK_MAX_FILL_LPH: &'static str = match option_env!("RP_TANK_MAX_FILL_LPH") {
+    Some(rate) => rate,
+    _ => "1000",
+};
+// comma separated `level_mm:litres` pairs, e.g. "0:0,500:180,1000:400"
+pub const TANK_LOOKUP: &'static str = match option_env!("RP_TANK_LOOKUP") {
+    Some(lookup) => lookup,
+    _ => "",
+};
+
+// counter and direction mode
+pub const COUNTER_BREAK_DISTANCE_MM: &'static str =
+    match option_env!("RP_COUNTER_BREAK_DISTANCE_MM") {
+        Some(distance) => distance,
+        _ => "700",
+    };
+pub const COUNTER_MIN_GAP_MS: &'static str = match option_env!("RP_COUNTER_MIN_GAP_MS") {
+    Some(gap) => gap,
+    _ => "500",
+};
+pub const COUNTER_INTERVAL_SECS: &'static str = match option_env!("RP_COUNTER_INTERVAL_SECS") {
+    Some(interval) => interval,
+    _ => "60",
+};
+
+// parking mode
+pub const PARKING_STOP_DISTANCE_MM: &'static str = match option_env!("RP_PARKING_STOP_DISTANCE_MM")
+{
+    Some(distance) => distance,
+    _ => "500",
+};
+pub const PARKING_APPROACH_MM: &'static str = match option_env!("RP_PARKING_APPROACH_MM") {
+    Some(distance) => distance,
+    _ => "1000",
+};
+pub const PARKING_TOLERANCE_MM: &'static str = match option_env!("RP_PARKING_TOLERANCE_MM") {
+    Some(distance) => distance,
+    _ => "100",
+};
+pub const PARKING_IDLE_SECS: &'static str = match option_env!("RP_PARKING_IDLE_SECS") {
+    Some(idle) => idle,
+    _ => "30",
+};
+pub const PARKING_EXTERNAL_LEDS: &'static str = match option_env!("RP_PARKING_EXTERNAL_LEDS") {
+    Some(enabled) => enabled,
+    _ => "false",
+};
+
+// door mode
+pub const DOOR_DEBOUNCE_MS: &'static str = match option_env!("RP_DOOR_DEBOUNCE_MS") {
+    Some(debounce) => debounce,
+    _ => "500",
+};
+pub const DOOR_INTERVAL_SECS: &'static str = match option_env!("RP_DOOR_INTERVAL_SECS") {
+    Some(interval) => interval,
+    _ => "10",
+};
+
+// scan mode, a servo on GP18 sweeps the sensor between the start and end angle
+pub const SCAN_START_DEGREES: &'static str = match option_env!("RP_SCAN_START_DEGREES") {
+    Some(degrees) => degrees,
+    _ => "0",
+};
+pub const SCAN_END_DEGREES: &'static str = match option_env!("RP_SCAN_END_DEGREES") {
+    Some(degrees) => degrees,
+    _ => "180",
+};
+pub const SCAN_STEP_DEGREES: &'static str = match option_env!("RP_SCAN_STEP_DEGREES") {
+    Some(degrees) => degrees,
+    _ => "6",
+};
+pub const SCAN_THRESHOLD_MM: &'static str = match option_env!("RP_SCAN_THRESHOLD_MM") {
+    Some(distance) => distance,
+    _ => "200",
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Mode {
+    Intrusion,
+    Tank,
+    Counter,
+    Direction,
+    Parking,
+    Scan,
+    Door,
+}
+
+impl FromStr for Mode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "intrusion" => Ok(Mode::Intrusion),
+            "tank" => Ok(Mode::Tank),
+            "counter" => Ok(Mode::Counter),
+            "direction" => Ok(Mode::Direction),
+            "parking" => Ok(Mode::Parking),
+            "scan" => Ok(Mode::Scan),
+            "door" => Ok(Mode::Door),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Detector {
+    // something came 200mm closer than the learned baseline
+    Threshold,
+    // the reading is unusual for the time of day
+    Anomaly,
+}
+
+impl FromStr for Detector {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "threshold" => Ok(Detector::Threshold),
+            "anomaly" => Ok(Detector::Anomaly),
+            _ => Err(()),
+        }
+    }
+}
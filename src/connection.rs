@@ -0,0 +1,153 @@
+use embassy_time::{Duration, Instant};
+
+// Delays between connection attempts, doubling from `min` up to `max`.
+// Every delay is shortened by a random amount so a broker restart
+// doesn't get all picos reconnecting at the same moment.
+pub struct Backoff {
+    min: Duration,
+    max: Duration,
+    current: Duration,
+    // when the first attempt of the current outage failed
+    outage_started: Option<Instant>,
+    // xorshift state
+    random: u32,
+}
+
+impl Backoff {
+    pub fn new(min: Duration, max: Duration, seed: u32) -> Self {
+        Self {
+            min,
+            max,
+            current: min,
+            outage_started: None,
+            // xorshift never leaves zero
+            random: seed | 1,
+        }
+    }
+
+    pub fn next_delay(&mut self, now: Instant) -> Duration {
+        self.outage_started.get_or_insert(now);
+
+        self.random ^= self.random << 13;
+        self.random ^= self.random >> 17;
+        self.random ^= self.random << 5;
+        // somewhere between half and the full delay
+        let half = self.current.as_millis() / 2;
+        let delay = Duration::from_millis(half + self.random as u64 % (half + 1));
+
+        self.current = (self.current * 2).min(self.max);
+        delay
+    }
+
+    // how long the broker has been unreachable
+    pub fn outage(&self, now: Instant) -> Duration {
+        match self.outage_started {
+            Some(started) => now.duration_since(started),
+            None => Duration::from_ticks(0),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.current = self.min;
+        self.outage_started = None;
+    }
+}
+
+// Health of the current mqtt session. A failed publish, receive
+// or ping breaks it and the pico connects to the broker again.
+pub struct Session {
+    broken: bool,
+    last_active: Instant,
+    ping_interval: Duration,
+}
+
+impl Session {
+    pub fn new(ping_interval: Duration, now: Instant) -> Self {
+        Self {
+            broken: false,
+            last_active: now,
+            ping_interval,
+        }
+    }
+
+    pub fn is_broken(&self) -> bool {
+        self.broken
+    }
+
+    pub fn fail(&mut self) {
+        self.broken = true;
+    }
+
+    pub fn active(&mut self, now: Instant) {
+        self.last_active = now;
+    }
+
+    // a quiet session is pinged to find out whether the broker is still there
+    pub fn ping_at(&self) -> Instant {
+        self.last_active + self.ping_interval
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delays_double_up_to_the_maximum() {
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(8), 42);
+        let now = Instant::from_secs(100);
+        for max in [1, 2, 4, 8, 8, 8] {
+            let delay = backoff.next_delay(now);
+            assert!(delay >= Duration::from_millis(max * 500), "{:?}", delay);
+            assert!(delay <= Duration::from_secs(max), "{:?}", delay);
+        }
+    }
+
+    #[test]
+    fn seeds_spread_the_delays() {
+        let now = Instant::from_secs(0);
+        let delays: [Duration; 4] = core::array::from_fn(|seed| {
+            let mut backoff = Backoff::new(
+                Duration::from_secs(60),
+                Duration::from_secs(60),
+                seed as u32 * 7919,
+            );
+            backoff.next_delay(now)
+        });
+        assert!(delays.iter().any(|&delay| delay != delays[0]));
+    }
+
+    #[test]
+    fn outage_lasts_until_reset() {
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(60), 1);
+        assert_eq!(
+            backoff.outage(Instant::from_secs(5)),
+            Duration::from_secs(0)
+        );
+
+        backoff.next_delay(Instant::from_secs(10));
+        backoff.next_delay(Instant::from_secs(20));
+        assert_eq!(
+            backoff.outage(Instant::from_secs(30)),
+            Duration::from_secs(20)
+        );
+
+        backoff.reset();
+        assert_eq!(
+            backoff.outage(Instant::from_secs(30)),
+            Duration::from_secs(0)
+        );
+        assert!(backoff.next_delay(Instant::from_secs(40)) <= Duration::from_secs(1));
+    }
+
+    #[test]
+    fn quiet_sessions_are_pinged() {
+        let mut session = Session::new(Duration::from_secs(60), Instant::from_secs(0));
+        assert_eq!(session.ping_at(), Instant::from_secs(60));
+        session.active(Instant::from_secs(30));
+        assert_eq!(session.ping_at(), Instant::from_secs(90));
+        assert!(!session.is_broken());
+        session.fail();
+        assert!(session.is_broken());
+    }
+}
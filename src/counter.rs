@@ -0,0 +1,105 @@
+use embassy_time::{Duration, Instant};
+
+const HOUR: Duration = Duration::from_secs(3600);
+
+pub struct PassageCounter {
+    // the beam counts as broken below this distance
+    break_distance: f64,
+    // passages closer together than this are counted once
+    min_gap: Duration,
+    broken: bool,
+    last_passage: Option<Instant>,
+    total: u32,
+    hour_started: Instant,
+    hour_count: u32,
+}
+
+impl PassageCounter {
+    pub fn new(break_distance: f64, min_gap: Duration, total: u32, now: Instant) -> Self {
+        Self {
+            break_distance,
+            min_gap,
+            broken: false,
+            last_passage: None,
+            total,
+            hour_started: now,
+            hour_count: 0,
+        }
+    }
+
+    // returns true when the reading completes a passage,
+    // that is the beam was broken and has now been restored
+    pub fn update(&mut self, now: Instant, distance: f64) -> bool {
+        if distance < self.break_distance {
+            self.broken = true;
+            return false;
+        }
+        if !self.broken {
+            return false;
+        }
+        self.broken = false;
+
+        if let Some(last_passage) = self.last_passage {
+            if now.duration_since(last_passage) < self.min_gap {
+                return false;
+            }
+        }
+
+        self.last_passage = Some(now);
+        self.total = self.total.wrapping_add(1);
+        self.hour_count += 1;
+        true
+    }
+
+    // returns the count of the previous hour once it has finished
+    pub fn finish_hour(&mut self, now: Instant) -> Option<u32> {
+        if now.duration_since(self.hour_started) < HOUR {
+            return None;
+        }
+
+        let count = self.hour_count;
+        self.hour_started += HOUR;
+        self.hour_count = 0;
+        Some(count)
+    }
+
+    pub fn total(&self) -> u32 {
+        self.total
+    }
+
+    pub fn hour_count(&self) -> u32 {
+        self.hour_count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_restored_beams_from_the_persisted_total() {
+        let mut counter =
+            PassageCounter::new(700.0, Duration::from_millis(500), 41, Instant::from_secs(0));
+        assert!(!counter.update(Instant::from_millis(0), 400.0));
+        assert!(counter.update(Instant::from_millis(100), 1000.0));
+        // the same person breaking the beam twice
+        assert!(!counter.update(Instant::from_millis(200), 400.0));
+        assert!(!counter.update(Instant::from_millis(300), 1000.0));
+        assert!(!counter.update(Instant::from_millis(900), 400.0));
+        assert!(counter.update(Instant::from_millis(1000), 1000.0));
+        assert_eq!(counter.total(), 43);
+        assert_eq!(counter.hour_count(), 2);
+    }
+
+    #[test]
+    fn finishes_every_hour() {
+        let mut counter =
+            PassageCounter::new(700.0, Duration::from_millis(500), 0, Instant::from_secs(0));
+        counter.update(Instant::from_secs(10), 400.0);
+        counter.update(Instant::from_secs(11), 1000.0);
+        assert_eq!(counter.finish_hour(Instant::from_secs(3599)), None);
+        assert_eq!(counter.finish_hour(Instant::from_secs(3600)), Some(1));
+        assert_eq!(counter.hour_count(), 0);
+        assert_eq!(counter.total(), 1);
+    }
+}
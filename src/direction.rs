@@ -0,0 +1,180 @@
+use embassy_time::{Duration, Instant};
+
+// Two sensors are mounted a short distance apart along a corridor,
+// sensor `A` on the outside and sensor `B` on the inside. The order
+// in which the beams are broken gives the direction of travel.
+
+// someone standing in the beams for this long is no longer a passage
+const MAX_PASSAGE: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Beam {
+    A,
+    B,
+}
+
+pub struct DirectionDetector {
+    // a beam counts as broken below this distance
+    break_distance: f64,
+    // the beam that was broken first and when
+    first: Option<(Beam, Instant)>,
+    // whether the other beam was broken during the passage
+    crossed: bool,
+    inbound: u32,
+    outbound: u32,
+    occupancy: u32,
+}
+
+impl DirectionDetector {
+    pub fn new(break_distance: f64) -> Self {
+        Self {
+            break_distance,
+            first: None,
+            crossed: false,
+            inbound: 0,
+            outbound: 0,
+            occupancy: 0,
+        }
+    }
+
+    // returns the direction once a passage through both beams has completed
+    pub fn update(&mut self, now: Instant, a: f64, b: f64) -> Option<Direction> {
+        let a_broken = a < self.break_distance;
+        let b_broken = b < self.break_distance;
+
+        let Some((first, started)) = self.first else {
+            // both beams broken at once tells us nothing about the direction
+            self.first = match (a_broken, b_broken) {
+                (true, false) => Some((Beam::A, now)),
+                (false, true) => Some((Beam::B, now)),
+                _ => None,
+            };
+            self.crossed = false;
+            return None;
+        };
+
+        if now.duration_since(started) > MAX_PASSAGE {
+            self.first = None;
+            return None;
+        }
+
+        match first {
+            Beam::A if b_broken => self.crossed = true,
+            Beam::B if a_broken => self.crossed = true,
+            _ => (),
+        }
+
+        if a_broken || b_broken {
+            return None;
+        }
+
+        // both beams restored, someone turning around
+        // before reaching the second beam is not counted
+        self.first = None;
+        if !self.crossed {
+            return None;
+        }
+
+        Some(match first {
+            Beam::A => {
+                self.inbound = self.inbound.wrapping_add(1);
+                self.occupancy = self.occupancy.saturating_add(1);
+                Direction::Inbound
+            }
+            Beam::B => {
+                self.outbound = self.outbound.wrapping_add(1);
+                // never negative when someone was already
+                // inside before the pico started counting
+                self.occupancy = self.occupancy.saturating_sub(1);
+                Direction::Outbound
+            }
+        })
+    }
+
+    pub fn inbound(&self) -> u32 {
+        self.inbound
+    }
+
+    pub fn outbound(&self) -> u32 {
+        self.outbound
+    }
+
+    pub fn occupancy(&self) -> u32 {
+        self.occupancy
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FAR: f64 = 2000.0;
+    const NEAR: f64 = 300.0;
+
+    // feeds the (a, b) readings one second apart, returns what the last one completed
+    fn pass(
+        detector: &mut DirectionDetector,
+        start: u64,
+        beams: &[(f64, f64)],
+    ) -> Option<Direction> {
+        let mut direction = None;
+        for (second, &(a, b)) in beams.iter().enumerate() {
+            direction = detector.update(Instant::from_secs(start + second as u64), a, b);
+        }
+        direction
+    }
+
+    #[test]
+    fn the_first_broken_beam_gives_the_direction() {
+        let mut detector = DirectionDetector::new(1000.0);
+        let inbound = [(NEAR, FAR), (NEAR, NEAR), (FAR, NEAR), (FAR, FAR)];
+        assert_eq!(pass(&mut detector, 0, &inbound), Some(Direction::Inbound));
+        let outbound = [(FAR, NEAR), (NEAR, NEAR), (NEAR, FAR), (FAR, FAR)];
+        assert_eq!(
+            pass(&mut detector, 10, &outbound),
+            Some(Direction::Outbound)
+        );
+
+        assert_eq!(pass(&mut detector, 20, &inbound), Some(Direction::Inbound));
+        assert_eq!(detector.inbound(), 2);
+        assert_eq!(detector.outbound(), 1);
+        assert_eq!(detector.occupancy(), 1);
+    }
+
+    #[test]
+    fn turning_around_is_not_a_passage() {
+        let mut detector = DirectionDetector::new(1000.0);
+        assert_eq!(pass(&mut detector, 0, &[(NEAR, FAR), (FAR, FAR)]), None);
+        // both at once tells nothing about the direction
+        assert_eq!(pass(&mut detector, 10, &[(NEAR, NEAR), (FAR, FAR)]), None);
+        assert_eq!(detector.inbound() + detector.outbound(), 0);
+    }
+
+    #[test]
+    fn standing_in_the_beams_is_not_a_passage() {
+        let mut detector = DirectionDetector::new(1000.0);
+        detector.update(Instant::from_secs(0), NEAR, FAR);
+        detector.update(Instant::from_secs(1), NEAR, NEAR);
+        assert_eq!(detector.update(Instant::from_secs(12), FAR, FAR), None);
+        assert_eq!(detector.inbound(), 0);
+    }
+
+    #[test]
+    fn occupancy_never_drops_below_zero() {
+        let mut detector = DirectionDetector::new(1000.0);
+        let outbound = [(FAR, NEAR), (NEAR, NEAR), (NEAR, FAR), (FAR, FAR)];
+        assert_eq!(pass(&mut detector, 0, &outbound), Some(Direction::Outbound));
+        assert_eq!(
+            pass(&mut detector, 10, &outbound),
+            Some(Direction::Outbound)
+        );
+        assert_eq!(detector.occupancy(), 0);
+        assert_eq!(detector.outbound(), 2);
+    }
+}
@@ -0,0 +1,385 @@
+use core::fmt::{Error, Write};
+
+use heapless::String;
+use serde::Serialize;
+
+use crate::topic::{Topic, Topics};
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+const MODEL: &str = "Pico W + HC-SR04";
+const MANUFACTURER: &str = "Raspberry Pi";
+const FIRMWARE: &str = env!("CARGO_PKG_VERSION");
+
+// Home Assistant picks up retained messages on
+// `<prefix>/<component>/<device id>/<object id>/config`
+// and creates the entities without any yaml.
+pub struct Entity {
+    component: &'static str,
+    object_id: &'static str,
+    name: &'static str,
+    // relative to the device topic
+    state: &'static str,
+    value_template: Option<&'static str>,
+    unit: Option<&'static str>,
+    device_class: Option<&'static str>,
+    state_class: Option<&'static str>,
+    payload_on: Option<&'static str>,
+    payload_off: Option<&'static str>,
+    // unavailable while the pico is offline or asleep
+    available: bool,
+}
+
+// a value published as json with its unit, see `payload::Measurement`
+const fn measurement(
+    object_id: &'static str,
+    name: &'static str,
+    state: &'static str,
+    unit: &'static str,
+    device_class: Option<&'static str>,
+    state_class: &'static str,
+) -> Entity {
+    Entity {
+        component: "sensor",
+        object_id,
+        name,
+        state,
+        value_template: Some("{{ value_json.value }}"),
+        unit: Some(unit),
+        device_class,
+        state_class: Some(state_class),
+        payload_on: None,
+        payload_off: None,
+        available: true,
+    }
+}
+
+// intrusion mode
+pub const DISTANCE: Entity = Entity {
+    component: "sensor",
+    object_id: "distance",
+    name: "Distance",
+    state: "~/distance",
+    value_template: Some("{{ value_json.distance }}"),
+    // the configured distance unit
+    unit: None,
+    device_class: Some("distance"),
+    state_class: Some("measurement"),
+    payload_on: None,
+    payload_off: None,
+    available: true,
+};
+
+// something closer than the baseline or an anomalous reading
+pub const PRESENCE: Entity = Entity {
+    component: "binary_sensor",
+    object_id: "presence",
+    name: "Presence",
+    state: "~/presence",
+    value_template: None,
+    unit: None,
+    device_class: Some("occupancy"),
+    state_class: None,
+    payload_on: Some("detected"),
+    payload_off: Some("clear"),
+    available: true,
+};
+
+pub const SPEED: Entity = measurement(
+    "speed",
+    "Speed",
+    "~/speed",
+    "m/s",
+    Some("speed"),
+    "measurement",
+);
+
+// tank mode
+pub const LEVEL: Entity = measurement(
+    "level",
+    "Level",
+    "~/level",
+    "mm",
+    Some("distance"),
+    "measurement",
+);
+pub const PERCENTAGE: Entity = measurement(
+    "percentage",
+    "Fill level",
+    "~/percentage",
+    "%",
+    None,
+    "measurement",
+);
+pub const LITRES: Entity = measurement(
+    "litres",
+    "Volume",
+    "~/litres",
+    "L",
+    Some("volume_storage"),
+    "measurement",
+);
+pub const RATE: Entity = measurement("rate", "Fill rate", "~/rate", "L/h", None, "measurement");
+
+// counter mode
+pub const COUNT: Entity = measurement(
+    "count",
+    "Passages",
+    "~/count",
+    "passages",
+    None,
+    "total_increasing",
+);
+// starts from zero every hour
+pub const COUNT_HOUR: Entity = measurement(
+    "count_hour",
+    "Passages this hour",
+    "~/count-hour",
+    "passages",
+    None,
+    "total_increasing",
+);
+
+// direction mode
+pub const INBOUND: Entity = measurement(
+    "inbound",
+    "Inbound",
+    "~/in",
+    "passages",
+    None,
+    "total_increasing",
+);
+pub const OUTBOUND: Entity = measurement(
+    "outbound",
+    "Outbound",
+    "~/out",
+    "passages",
+    None,
+    "total_increasing",
+);
+pub const OCCUPANCY: Entity = measurement(
+    "occupancy",
+    "Occupancy",
+    "~/occupancy",
+    "people",
+    None,
+    "measurement",
+);
+
+// parking mode, the traffic light status
+pub const PARKING: Entity = Entity {
+    component: "sensor",
+    object_id: "parking",
+    name: "Parking",
+    state: "~/parking",
+    value_template: Some("{{ value_json.state }}"),
+    unit: None,
+    device_class: None,
+    state_class: None,
+    payload_on: None,
+    payload_off: None,
+    available: true,
+};
+
+// online, offline or sleep
+pub const STATUS: Entity = Entity {
+    component: "sensor",
+    object_id: "status",
+    name: "Status",
+    state: "~/status",
+    value_template: None,
+    unit: None,
+    device_class: None,
+    state_class: None,
+    payload_on: None,
+    payload_off: None,
+    available: false,
+};
+
+// a covered or broken sensor
+pub const HEALTH: Entity = Entity {
+    component: "binary_sensor",
+    object_id: "health",
+    name: "Sensor problem",
+    state: "~/tamper",
+    value_template: None,
+    unit: None,
+    device_class: Some("problem"),
+    state_class: None,
+    payload_on: Some("tampered"),
+    payload_off: Some("ok"),
+    available: true,
+};
+
+// door mode
+pub const DOOR: Entity = Entity {
+    component: "binary_sensor",
+    object_id: "door",
+    name: "Door",
+    state: "~/door",
+    value_template: None,
+    unit: None,
+    device_class: Some("door"),
+    state_class: None,
+    payload_on: Some("open"),
+    payload_off: Some("closed"),
+    available: true,
+};
+
+pub const DOOR_OPEN: Entity = measurement(
+    "door_open",
+    "Door open",
+    "~/door-open-secs",
+    "s",
+    Some("duration"),
+    "measurement",
+);
+
+// the abbreviated keys keep a config within a single mqtt packet
+#[derive(Serialize)]
+struct Config<'a> {
+    name: &'a str,
+    #[serde(rename = "~")]
+    base: &'a str,
+    uniq_id: &'a str,
+    stat_t: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    val_tpl: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unit_of_meas: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dev_cla: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stat_cla: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pl_on: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pl_off: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    avty_t: Option<&'a str>,
+    dev: Device<'a>,
+}
+
+#[derive(Serialize)]
+struct Device<'a> {
+    ids: [&'a str; 1],
+    name: &'a str,
+    mdl: &'a str,
+    mf: &'a str,
+    sw: &'a str,
+    cns: [[&'a str; 2]; 1],
+}
+
+impl Entity {
+    pub fn topic(&self, prefix: &str, device_id: &str) -> Result<Topic> {
+        let mut topic = Topic::new();
+        write!(
+            topic,
+            "{}/{}/{}/{}/config",
+            prefix, self.component, device_id, self.object_id
+        )?;
+        Ok(topic)
+    }
+
+    pub fn config<const N: usize>(
+        &self,
+        topics: &Topics,
+        device_id: &str,
+        mac_addr: &str,
+        distance_unit: &str,
+    ) -> Result<String<N>> {
+        let mut unique_id: String<32> = String::new();
+        write!(unique_id, "{}_{}", device_id, self.object_id)?;
+        let mut device_name: String<32> = String::new();
+        write!(device_name, "pico {}", device_id)?;
+
+        let config = Config {
+            name: self.name,
+            base: topics.base(),
+            uniq_id: &unique_id,
+            stat_t: self.state,
+            val_tpl: self.value_template,
+            unit_of_meas: self
+                .unit
+                .or((self.device_class == Some("distance")).then_some(distance_unit)),
+            dev_cla: self.device_class,
+            stat_cla: self.state_class,
+            pl_on: self.payload_on,
+            pl_off: self.payload_off,
+            avty_t: self.available.then_some("~/status"),
+            dev: Device {
+                ids: [device_id],
+                name: &device_name,
+                mdl: MODEL,
+                mf: MANUFACTURER,
+                sw: FIRMWARE,
+                cns: [["mac", mac_addr]],
+            },
+        };
+        serde_json_core::to_string(&config).map_err(|_| Error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config_of(entity: &Entity) -> String<512> {
+        let topics = Topics::new("pico", "28cdc1000001").unwrap();
+        entity
+            .config(&topics, "28cdc1000001", "28:cd:c1:00:00:01", "cm")
+            .unwrap()
+    }
+
+    #[test]
+    fn topic_below_the_discovery_prefix() {
+        assert_eq!(
+            PRESENCE
+                .topic("homeassistant", "28cdc1000001")
+                .unwrap()
+                .as_str(),
+            "homeassistant/binary_sensor/28cdc1000001/presence/config"
+        );
+    }
+
+    #[test]
+    fn presence_has_both_payloads() {
+        let config = config_of(&PRESENCE);
+        assert!(config.contains(r#""stat_t":"~/presence""#));
+        assert!(config.contains(r#""pl_on":"detected","pl_off":"clear""#));
+    }
+
+    #[test]
+    fn measurements_read_the_value_in_their_own_unit() {
+        let config = config_of(&LEVEL);
+        assert!(config.contains(r#""val_tpl":"{{ value_json.value }}","unit_of_meas":"mm""#));
+        // the distance follows the configured unit
+        assert!(config_of(&DISTANCE).contains(r#""unit_of_meas":"cm""#));
+    }
+
+    #[test]
+    fn every_config_fits_in_an_mqtt_payload() {
+        for entity in [
+            &DISTANCE,
+            &PRESENCE,
+            &SPEED,
+            &LEVEL,
+            &PERCENTAGE,
+            &LITRES,
+            &RATE,
+            &COUNT,
+            &COUNT_HOUR,
+            &INBOUND,
+            &OUTBOUND,
+            &OCCUPANCY,
+            &PARKING,
+            &STATUS,
+            &HEALTH,
+            &DOOR,
+            &DOOR_OPEN,
+        ] {
+            // MQTT_PAYLOAD_SIZE
+            assert!(config_of(entity).len() <= 448, "{}", entity.object_id);
+        }
+    }
+}
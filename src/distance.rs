@@ -0,0 +1,102 @@
+use embassy_time::Duration;
+
+// How the sensor is mounted relative to the point we measure from,
+// for example the tank floor or a door frame.
+#[derive(Clone, Copy, Default)]
+pub struct Mount {
+    // added to the distance, negative when the sensor
+    // sits in front of the reference point
+    pub offset_mm: f64,
+    // angle between the sensor and the perpendicular
+    pub tilt_degrees: f64,
+}
+
+impl Mount {
+    pub fn correct(&self, unit: Unit) -> Unit {
+        // clamped readings have no real distance to correct
+        if unit.status != Status::Valid {
+            return unit;
+        }
+
+        let radians = self.tilt_degrees.to_radians();
+        let millimeters = (unit.millimeters * libm::cos(radians) + self.offset_mm).max(0.0);
+        Unit {
+            millimeters,
+            centimeters: millimeters / 10.0,
+            decimeters: millimeters / 100.0,
+            meters: millimeters / 1000.0,
+            ..unit
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Status {
+    Valid,
+    // echo shorter than 2cm, the sensor might be covered
+    TooClose,
+    // nothing within 4m
+    OutOfRange,
+    // the echo pin never responded
+    NoEcho,
+}
+
+impl Status {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Status::Valid => "valid",
+            Status::TooClose => "too-close",
+            Status::OutOfRange => "out-of-range",
+            Status::NoEcho => "no-echo",
+        }
+    }
+}
+
+pub struct Unit {
+    pub millimeters: f64,
+    pub centimeters: f64,
+    pub decimeters: f64,
+    pub meters: f64,
+    // duration of the echo pulse
+    pub echo: Duration,
+    pub status: Status,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit(millimeters: f64, status: Status) -> Unit {
+        Unit {
+            millimeters,
+            centimeters: millimeters / 10.0,
+            decimeters: millimeters / 100.0,
+            meters: millimeters / 1000.0,
+            echo: Duration::from_micros(0),
+            status,
+        }
+    }
+
+    #[test]
+    fn corrects_tilt_and_offset() {
+        let mount = Mount {
+            offset_mm: -50.0,
+            tilt_degrees: 60.0,
+        };
+        let corrected = mount.correct(unit(1000.0, Status::Valid));
+        assert!((corrected.millimeters - 450.0).abs() < 1e-9);
+        assert!((corrected.meters - 0.45).abs() < 1e-9);
+        // never behind the reference point
+        assert_eq!(mount.correct(unit(10.0, Status::Valid)).millimeters, 0.0);
+    }
+
+    #[test]
+    fn leaves_clamped_readings_alone() {
+        let mount = Mount {
+            offset_mm: 100.0,
+            tilt_degrees: 0.0,
+        };
+        let corrected = mount.correct(unit(4000.0, Status::OutOfRange));
+        assert_eq!(corrected.millimeters, 4000.0);
+    }
+}
@@ -0,0 +1,199 @@
+use core::str::FromStr;
+
+use embassy_time::{Duration, Instant};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DoorState {
+    Open,
+    Closed,
+}
+
+impl DoorState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DoorState::Open => "open",
+            DoorState::Closed => "closed",
+        }
+    }
+}
+
+// `learn-open` and `learn-closed` store the current reading as the reference
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Command {
+    LearnOpen,
+    LearnClosed,
+}
+
+impl Command {
+    // the state whose reference is learned
+    pub fn state(&self) -> DoorState {
+        match self {
+            Command::LearnOpen => DoorState::Open,
+            Command::LearnClosed => DoorState::Closed,
+        }
+    }
+}
+
+impl FromStr for Command {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "learn-open" => Ok(Command::LearnOpen),
+            "learn-closed" => Ok(Command::LearnClosed),
+            _ => Err(()),
+        }
+    }
+}
+
+pub struct Door {
+    // learned distances with the door open and closed
+    open: Option<f64>,
+    closed: Option<f64>,
+    debounce: Duration,
+    state: Option<DoorState>,
+    // a state change waiting for the debounce time to pass
+    candidate: Option<(DoorState, Instant)>,
+    changed_at: Instant,
+}
+
+impl Door {
+    pub fn new(open: Option<f64>, closed: Option<f64>, debounce: Duration, now: Instant) -> Self {
+        Self {
+            open,
+            closed,
+            debounce,
+            state: None,
+            candidate: None,
+            changed_at: now,
+        }
+    }
+
+    // stored as the open and closed distance, NaN when not learned yet
+    pub fn from_bytes(bytes: [u8; 16], debounce: Duration, now: Instant) -> Self {
+        let reference = |bytes: &[u8]| {
+            let mut buf = [0u8; 8];
+            buf.copy_from_slice(bytes);
+            Some(f64::from_le_bytes(buf)).filter(|distance| !distance.is_nan())
+        };
+        Self::new(
+            reference(&bytes[..8]),
+            reference(&bytes[8..]),
+            debounce,
+            now,
+        )
+    }
+
+    pub fn to_bytes(&self) -> [u8; 16] {
+        let mut bytes = [0u8; 16];
+        bytes[..8].copy_from_slice(&self.open.unwrap_or(f64::NAN).to_le_bytes());
+        bytes[8..].copy_from_slice(&self.closed.unwrap_or(f64::NAN).to_le_bytes());
+        bytes
+    }
+
+    pub fn learn(&mut self, state: DoorState, millimeters: f64) {
+        match state {
+            DoorState::Open => self.open = Some(millimeters),
+            DoorState::Closed => self.closed = Some(millimeters),
+        }
+        // classify from scratch with the new references
+        self.candidate = None;
+    }
+
+    pub fn is_learned(&self) -> bool {
+        self.open.is_some() && self.closed.is_some()
+    }
+
+    // returns the new state once it has held for the debounce time
+    pub fn update(&mut self, now: Instant, millimeters: f64) -> Option<DoorState> {
+        let (Some(open), Some(closed)) = (self.open, self.closed) else {
+            return None;
+        };
+
+        // whichever reference the reading is closest to
+        let reading = if libm::fabs(millimeters - open) < libm::fabs(millimeters - closed) {
+            DoorState::Open
+        } else {
+            DoorState::Closed
+        };
+        if Some(reading) == self.state {
+            self.candidate = None;
+            return None;
+        }
+
+        let since = match self.candidate {
+            Some((candidate, since)) if candidate == reading => since,
+            _ => {
+                self.candidate = Some((reading, now));
+                now
+            }
+        };
+        if now.duration_since(since) < self.debounce {
+            return None;
+        }
+
+        self.candidate = None;
+        self.state = Some(reading);
+        self.changed_at = since;
+        Some(reading)
+    }
+
+    pub fn open_for(&self, now: Instant) -> Duration {
+        match self.state {
+            Some(DoorState::Open) => now.duration_since(self.changed_at),
+            _ => Duration::from_ticks(0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEBOUNCE: Duration = Duration::from_millis(500);
+
+    fn learned() -> Door {
+        let mut door = Door::new(None, None, DEBOUNCE, Instant::from_secs(0));
+        door.learn(Command::LearnOpen.state(), 300.0);
+        door.learn(Command::LearnClosed.state(), 800.0);
+        door
+    }
+
+    #[test]
+    fn parses_the_learn_commands() {
+        assert_eq!(Command::from_str("learn-open\n"), Ok(Command::LearnOpen));
+        assert_eq!(Command::from_str("learn-closed"), Ok(Command::LearnClosed));
+        assert_eq!(Command::from_str("open"), Err(()));
+    }
+
+    #[test]
+    fn changes_state_once_debounced() {
+        let mut door = learned();
+        assert_eq!(door.update(Instant::from_millis(0), 790.0), None);
+        assert_eq!(
+            door.update(Instant::from_millis(500), 810.0),
+            Some(DoorState::Closed)
+        );
+        // a single reading close to the other reference is ignored
+        assert_eq!(door.update(Instant::from_millis(1000), 320.0), None);
+        assert_eq!(door.update(Instant::from_millis(1100), 790.0), None);
+        assert_eq!(door.update(Instant::from_millis(2000), 320.0), None);
+        assert_eq!(
+            door.update(Instant::from_millis(2600), 310.0),
+            Some(DoorState::Open)
+        );
+        assert_eq!(
+            door.open_for(Instant::from_millis(5000)),
+            Duration::from_millis(3000)
+        );
+    }
+
+    #[test]
+    fn keeps_the_references_across_resets() {
+        let door = learned();
+        let restored = Door::from_bytes(door.to_bytes(), DEBOUNCE, Instant::from_secs(0));
+        assert!(restored.is_learned());
+        let empty = Door::new(None, None, DEBOUNCE, Instant::from_secs(0));
+        assert!(!Door::from_bytes(empty.to_bytes(), DEBOUNCE, Instant::from_secs(0)).is_learned());
+    }
+}
@@ -0,0 +1,69 @@
+use heapless::Deque;
+
+pub const MAX_WINDOW: usize = 9;
+
+// The median of the last readings, a single echo off the wrong
+// object doesn't move it the way it would move an average.
+pub struct MedianFilter {
+    window: usize,
+    readings: Deque<f64, MAX_WINDOW>,
+}
+
+impl MedianFilter {
+    pub fn new(window: usize) -> Self {
+        Self {
+            window: window.clamp(1, MAX_WINDOW),
+            readings: Deque::new(),
+        }
+    }
+
+    pub fn set_window(&mut self, window: usize) {
+        self.window = window.clamp(1, MAX_WINDOW);
+        while self.readings.len() > self.window {
+            self.readings.pop_front();
+        }
+    }
+
+    pub fn update(&mut self, millimeters: f64) -> f64 {
+        if self.readings.len() >= self.window {
+            self.readings.pop_front();
+        }
+        // cannot fail, there is room for at least one more
+        let _ = self.readings.push_back(millimeters);
+
+        let mut sorted = [0.0; MAX_WINDOW];
+        let len = self.readings.len();
+        for (slot, &reading) in sorted.iter_mut().zip(self.readings.iter()) {
+            *slot = reading;
+        }
+        let sorted = &mut sorted[..len];
+        sorted.sort_unstable_by(|a, b| a.total_cmp(b));
+        sorted[len / 2]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ignores_a_single_stray_echo() {
+        let mut median = MedianFilter::new(3);
+        assert_eq!(median.update(800.0), 800.0);
+        assert_eq!(median.update(4000.0), 4000.0);
+        assert_eq!(median.update(810.0), 810.0);
+        assert_eq!(median.update(805.0), 810.0);
+    }
+
+    #[test]
+    fn shrinking_the_window_keeps_the_latest_readings() {
+        let mut median = MedianFilter::new(5);
+        for reading in [100.0, 200.0, 300.0, 400.0, 500.0] {
+            median.update(reading);
+        }
+        median.set_window(1);
+        assert_eq!(median.update(50.0), 50.0);
+        median.set_window(100);
+        assert_eq!(median.window, MAX_WINDOW);
+    }
+}
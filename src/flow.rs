@@ -0,0 +1,185 @@
+use embassy_time::{Duration, Instant};
+use heapless::Deque;
+
+use crate::stats;
+
+pub const MAX_SAMPLES: usize = 32;
+
+// levels close to the top or bottom of the tank
+// make an alarm critical instead of a warning
+const CRITICAL_FULL_PERCENTAGE: f64 = 95.0;
+const CRITICAL_EMPTY_PERCENTAGE: f64 = 5.0;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Alarm {
+    // level dropping faster than allowed, a leak or more consumption than usual
+    Drain,
+    // level rising faster than allowed
+    Overflow,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Severity {
+    Warning,
+    Critical,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Alert {
+    pub alarm: Alarm,
+    pub severity: Severity,
+}
+
+impl Alert {
+    pub fn payload(&self) -> &'static str {
+        match (self.alarm, self.severity) {
+            (Alarm::Drain, Severity::Warning) => "drain:warning",
+            (Alarm::Drain, Severity::Critical) => "drain:critical",
+            (Alarm::Overflow, Severity::Warning) => "overflow:warning",
+            (Alarm::Overflow, Severity::Critical) => "overflow:critical",
+        }
+    }
+}
+
+pub struct FlowMonitor {
+    window: Duration,
+    // litres per hour
+    max_drain: f64,
+    max_fill: f64,
+    samples: Deque<(Instant, f64), MAX_SAMPLES>,
+}
+
+impl FlowMonitor {
+    pub fn new(window: Duration, max_drain: f64, max_fill: f64) -> Self {
+        Self {
+            window,
+            max_drain,
+            max_fill,
+            samples: Deque::new(),
+        }
+    }
+
+    // adds a reading and returns the fill rate in litres per hour,
+    // negative when draining. there is no rate until the samples
+    // span at least half of the window.
+    pub fn update(&mut self, now: Instant, litres: f64) -> Option<f64> {
+        // the samples are kept at least this far apart so they spread over the whole
+        // window however often the tank is measured, a newer reading replaces the
+        // latest sample until it is far enough from the one before
+        let spacing = self.window / MAX_SAMPLES as u32;
+        if let Some(&(previous, _)) = self.samples.iter().rev().nth(1) {
+            if now.duration_since(previous) < spacing {
+                self.samples.pop_back();
+            }
+        }
+
+        while let Some(&(instant, _)) = self.samples.front() {
+            if now.duration_since(instant) <= self.window && !self.samples.is_full() {
+                break;
+            }
+            self.samples.pop_front();
+        }
+        // cannot fail, there is always room after the loop above
+        let _ = self.samples.push_back((now, litres));
+
+        let (oldest, _) = *self.samples.front()?;
+        if now.duration_since(oldest) < self.window / 2 {
+            return None;
+        }
+
+        // litres per second to litres per hour
+        Some(stats::slope(&self.samples) * 3600.0)
+    }
+
+    pub fn alert(&self, rate: f64, percentage: f64) -> Option<Alert> {
+        if -rate > self.max_drain {
+            let severity = if percentage <= CRITICAL_EMPTY_PERCENTAGE {
+                Severity::Critical
+            } else {
+                Severity::Warning
+            };
+            return Some(Alert {
+                alarm: Alarm::Drain,
+                severity,
+            });
+        }
+
+        if rate > self.max_fill {
+            let severity = if percentage >= CRITICAL_FULL_PERCENTAGE {
+                Severity::Critical
+            } else {
+                Severity::Warning
+            };
+            return Some(Alert {
+                alarm: Alarm::Overflow,
+                severity,
+            });
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_secs(600);
+
+    // fills at 60 litres per hour, measured every `interval`
+    fn fill(flow: &mut FlowMonitor, interval: Duration, until: Duration) -> Option<f64> {
+        let mut elapsed = Duration::from_secs(0);
+        let mut rate = None;
+        while elapsed <= until {
+            let litres = 100.0 + elapsed.as_millis() as f64 / 60_000.0;
+            rate = flow.update(Instant::from_secs(0) + elapsed, litres);
+            elapsed += interval;
+        }
+        rate
+    }
+
+    #[test]
+    fn no_rate_before_half_of_the_window() {
+        let mut flow = FlowMonitor::new(WINDOW, 10.0, 100.0);
+        assert_eq!(
+            fill(&mut flow, Duration::from_secs(1), Duration::from_secs(290)),
+            None
+        );
+    }
+
+    #[test]
+    fn rate_from_frequent_readings() {
+        let mut flow = FlowMonitor::new(WINDOW, 10.0, 100.0);
+        let rate = fill(&mut flow, Duration::from_secs(1), Duration::from_secs(1200)).unwrap();
+        assert!((rate - 60.0).abs() < 0.1, "rate {}", rate);
+    }
+
+    #[test]
+    fn rate_from_sparse_readings() {
+        let mut flow = FlowMonitor::new(WINDOW, 10.0, 100.0);
+        let rate = fill(
+            &mut flow,
+            Duration::from_secs(60),
+            Duration::from_secs(1200),
+        )
+        .unwrap();
+        assert!((rate - 60.0).abs() < 0.1, "rate {}", rate);
+    }
+
+    #[test]
+    fn alerts_by_direction_and_level() {
+        let flow = FlowMonitor::new(WINDOW, 10.0, 100.0);
+        assert_eq!(flow.alert(-5.0, 50.0), None);
+        assert_eq!(
+            flow.alert(-20.0, 3.0),
+            Some(Alert {
+                alarm: Alarm::Drain,
+                severity: Severity::Critical
+            })
+        );
+        assert_eq!(
+            flow.alert(150.0, 50.0).map(|a| a.payload()),
+            Some("overflow:warning")
+        );
+    }
+}
@@ -7,32 +7,26 @@ pub type Result<T> = core::result::Result<T, Error>;
 // for embedded systems, especially the Raspberry Pi Pico
 // We avoid using the std library and instead use the
 // embassy crate for async programming
-use embassy_rp::{
-    gpio::{Input, Level, Output, Pull},
-    peripherals::{PIN_2, PIN_3},
-};
-use embassy_time::{Duration, Instant, Timer};
+use embassy_rp::gpio::{AnyPin, Input, Level, Output, Pin, Pull};
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+use rp_intrusion_rust::distance::{Status, Unit};
 
 pub struct HCSR04 {
-    trigger: Output<'static, PIN_2>,
-    echo: Input<'static, PIN_3>,
-}
-
-pub struct Unit {
-    pub millimeters: f64,
-    pub centimeters: f64,
-    pub decimeters: f64,
-    pub meters: f64,
+    trigger: Output<'static, AnyPin>,
+    echo: Input<'static, AnyPin>,
 }
 
 // 343 m/s
 // 0.0343 cm/microsecond
 const SPEED_OF_SOUND: f64 = 0.0343;
 
+// the echo pulse is at most 38ms long when nothing is in range
+const ECHO_TIMEOUT: Duration = Duration::from_millis(60);
+
 impl HCSR04 {
-    pub fn new(trigger_pin: PIN_2, echo_pin: PIN_3) -> Result<Self> {
-        let mut trigger = Output::new(trigger_pin, Level::Low);
-        let echo = Input::new(echo_pin, Pull::None);
+    pub fn new(trigger_pin: impl Pin, echo_pin: impl Pin) -> Result<Self> {
+        let mut trigger = Output::new(trigger_pin.degrade(), Level::Low);
+        let echo = Input::new(echo_pin.degrade(), Pull::None);
         trigger.set_low();
 
         Ok(Self { trigger, echo })
@@ -41,11 +35,18 @@ impl HCSR04 {
         // cannot calculate distance if no object is
         // detected between 100uS - 18mS
         if duration.as_micros() < 100 || duration.as_millis() > 18 {
+            let status = if duration.as_micros() < 100 {
+                Status::TooClose
+            } else {
+                Status::OutOfRange
+            };
             return Unit {
                 millimeters: 4000.0,
                 centimeters: 400.0,
                 decimeters: 40.0,
                 meters: 4.0,
+                echo: duration,
+                status,
             };
         }
 
@@ -61,6 +62,8 @@ impl HCSR04 {
                 centimeters: 0.0,
                 decimeters: 0.0,
                 meters: 0.0,
+                echo: duration,
+                status: Status::TooClose,
             };
         }
 
@@ -71,6 +74,8 @@ impl HCSR04 {
                 centimeters: 400.0,
                 decimeters: 40.0,
                 meters: 4.0,
+                echo: duration,
+                status: Status::OutOfRange,
             };
         }
 
@@ -79,8 +84,20 @@ impl HCSR04 {
             centimeters: distance,
             decimeters: distance / 10.0,
             meters: distance / 100.0,
+            echo: duration,
+            status: Status::Valid,
         };
     }
+    fn no_echo() -> Unit {
+        Unit {
+            millimeters: 4000.0,
+            centimeters: 400.0,
+            decimeters: 40.0,
+            meters: 4.0,
+            echo: Duration::from_ticks(0),
+            status: Status::NoEcho,
+        }
+    }
     pub async fn measure(&mut self) -> Result<Unit> {
         // prevent the sesor from being
         // triggered too often
@@ -89,10 +106,23 @@ impl HCSR04 {
         Timer::after(Duration::from_micros(10)).await;
         self.trigger.set_low();
 
-        self.echo.wait_for_high().await;
+        // a disconnected or broken sensor never raises
+        // the echo pin, don't wait for it forever
+        if with_timeout(ECHO_TIMEOUT, self.echo.wait_for_high())
+            .await
+            .is_err()
+        {
+            return Ok(Self::no_echo());
+        }
         let instant = Instant::now();
-        self.echo.wait_for_low().await;
+        if with_timeout(ECHO_TIMEOUT, self.echo.wait_for_low())
+            .await
+            .is_err()
+        {
+            return Ok(Self::no_echo());
+        }
 
+        // the mount is corrected for by the caller, tampering is judged on the raw echo
         return Ok(self.calculate_speed(instant.elapsed()));
     }
 }
@@ -0,0 +1,30 @@
+#![cfg_attr(not(test), no_std)]
+
+// everything that doesn't touch the hardware, the firmware itself is in main.rs
+pub mod anomaly;
+pub mod brokers;
+pub mod clock;
+pub mod command;
+pub mod connection;
+pub mod counter;
+pub mod direction;
+pub mod discovery;
+pub mod distance;
+pub mod door;
+pub mod filter;
+pub mod flow;
+pub mod hex;
+pub mod mdns;
+pub mod panel;
+pub mod parking;
+pub mod payload;
+pub mod pump;
+pub mod rules;
+pub mod settings;
+pub mod siren;
+pub mod speed;
+pub mod stats;
+pub mod sweep;
+pub mod tamper;
+pub mod tank;
+pub mod topic;
@@ -1,32 +1,82 @@
 #![no_std]
 #![no_main]
 
+pub mod buzzer;
+pub mod config;
 pub mod hc_sr04;
-pub mod hex;
+pub mod mqtt;
+pub mod resolver;
+pub mod scanner;
+pub mod sntp;
+pub mod storage;
+pub mod transport;
+
+use rp_intrusion_rust::{
+    anomaly, brokers, clock, command, connection, counter, direction, discovery, distance, door,
+    filter, flow, hex, mdns, panel, parking, payload, pump, rules, settings, siren, speed, sweep,
+    tamper, tank, topic,
+};
 
+use anomaly::AnomalyDetector;
+use brokers::Brokers;
+use buzzer::{Buzzer, SIREN};
 use byteorder::ByteOrder;
+use command::{Ack, Outcome};
+use config::{
+    Detector, Mode, MQTT_BROKERS, MQTT_PASSWORD, MQTT_SERVER, MQTT_SERVER_PORT, MQTT_USERNAME,
+    WIFI_NETWORK, WIFI_PASSWORD,
+};
+use connection::Backoff;
+use core::cell::RefCell;
+use core::fmt::Error;
+use core::fmt::Write;
+use core::future::poll_fn;
 use core::str::FromStr;
-use core::{env, option_env};
+use counter::PassageCounter;
 use cyw43_pio::PioSpi;
 use defmt::unwrap;
+use direction::{Direction, DirectionDetector};
+use discovery::Entity;
+use distance::Status;
+use door::Door;
 use embassy_executor::Spawner;
+use embassy_futures::select::{select, Either};
 use embassy_net::driver::Driver as NetDriver;
 use embassy_net::tcp::TcpSocket;
 use embassy_net::{Config, Ipv4Address, Stack, StackResources};
 use embassy_rp::bind_interrupts;
-use embassy_rp::gpio::{Level, Output};
+use embassy_rp::gpio::{AnyPin, Level, Output, Pin};
 use embassy_rp::peripherals::{DMA_CH0, PIN_23, PIN_25, PIO0, USB};
 use embassy_rp::pio::{InterruptHandler, Pio};
 use embassy_rp::usb::{Driver as USBDriver, InterruptHandler as USBInterruptHandler};
 use embassy_rp::watchdog::Watchdog;
-use embassy_time::{Duration, Timer};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal;
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+use filter::MedianFilter;
+use flow::FlowMonitor;
 use hc_sr04::HCSR04;
-use heapless::String;
-use hex::mac_addr_to_str;
-use rust_mqtt::client::client::MqttClient;
-use rust_mqtt::client::client_config::ClientConfig;
+use heapless::{String, Vec};
+use hex::{mac_addr_to_id, mac_addr_to_str};
+use mqtt::{publish, Link, MQTT_BUFFER_SIZE, MQTT_PAYLOAD_SIZE};
+use panel::{AlarmPanel, ArmMode, Command, State};
+use parking::{Lights, ParkingAssistant, Signal};
+use payload::{Measurement, Reading};
+use pump::Pump;
+use rules::{Action, Rules};
+use rust_mqtt::client::client_config::MqttVersion;
 use rust_mqtt::utils::rng_generator::CountingRng;
+use scanner::{Scanner, Servo};
+use settings::{DistanceUnit, Settings};
+use siren::{Pattern, Trigger};
+use speed::SpeedEstimator;
 use static_cell::StaticCell;
+use storage::{Slot, Storage};
+use tamper::TamperDetector;
+use tank::{Geometry, Tank};
+use topic::Topics;
+use transport::Buffers;
 
 // global logging
 use defmt_rtt as _;
@@ -90,27 +140,81 @@ use log::{debug, error, warn};
 
 type Device = cyw43::NetDriver<'static>;
 
+// the intrusion baseline grows with the readings, starting from next to nothing
+const INITIAL_BASE_LINE: f64 = 8.0;
+// quiet sessions are pinged this often to notice a broken connection
+const PING_INTERVAL: Duration = Duration::from_secs(60);
+
+// fed by the measurement task, either task can reset the pico
+static WATCHDOG: Mutex<CriticalSectionRawMutex, RefCell<Option<Watchdog>>> =
+    Mutex::new(RefCell::new(None));
+// the answer to `cmd/time`, the measurement task waits for it
+static START: signal::Signal<CriticalSectionRawMutex, Start> = signal::Signal::new();
+// measurements have started, with or without an answer on `cmd/time`
+static MEASURING: signal::Signal<CriticalSectionRawMutex, ()> = signal::Signal::new();
+// the pico has (re)connected, the measurement task publishes its retained state again
+static CONNECTED: signal::Signal<CriticalSectionRawMutex, ()> = signal::Signal::new();
+// what the measurement task needs from the connection
+static REQUEST: signal::Signal<CriticalSectionRawMutex, Request> = signal::Signal::new();
+
+enum Start {
+    Measure,
+    // seconds
+    Sleep(u64),
+}
+
+enum Request {
+    // the topics have changed
+    Reconnect(Settings),
+    // the discovery configs have changed, the connection stays up
+    Announce(Settings),
+    Reset,
+}
+
+// why a session with the broker ended on purpose
+enum End {
+    Sleep(u64),
+    Request(Request),
+}
+
+// Everything the measurement task owns. It keeps measuring, switching the
+// pump and sounding the siren while the connection to the broker is down.
+struct Station {
+    control: cyw43::Control<'static>,
+    mode: Mode,
+    detector: Detector,
+    siren_trigger: Trigger,
+    device_id: String<12>,
+    settings: Settings,
+    storage: Storage,
+    ultrasonic: HCSR04,
+    inner_ultrasonic: Option<HCSR04>,
+    median: MedianFilter,
+    tamper: TamperDetector,
+    rules: Rules,
+    rule_outputs: [Output<'static, AnyPin>; 2],
+    anomaly: AnomalyDetector,
+    speeds: SpeedEstimator,
+    panel: AlarmPanel,
+    tank: Option<Tank>,
+    flow: Option<FlowMonitor>,
+    pump: Option<Pump<Output<'static, AnyPin>>>,
+    passages: PassageCounter,
+    persisted_total: u32,
+    counter_interval: Duration,
+    directions: DirectionDetector,
+    parking: ParkingAssistant,
+    lights: Option<Lights<Output<'static, AnyPin>>>,
+    scanner: Option<Scanner>,
+    door: Door,
+    door_interval: Duration,
+}
+
 bind_interrupts!(struct Irqs {
     USBCTRL_IRQ => USBInterruptHandler<USB>;
     PIO0_IRQ_0 => InterruptHandler<PIO0>;
 });
 
-const WIFI_NETWORK: &'static str = env!("RP_WIFI_NETWORK");
-const WIFI_PASSWORD: &'static str = env!("RP_WIFI_PASSWORD");
-const MQTT_SERVER_IP: &'static str = env!("RP_MQTT_SERVER_IP");
-const MQTT_SERVER_PORT: &'static str = match option_env!("RP_MQTT_SERVER_PORT") {
-    Some(port) => port,
-    _ => "1883",
-};
-const MQTT_USERNAME: &'static str = match option_env!("RP_MQTT_USERNAME") {
-    Some(username) => username,
-    _ => "",
-};
-const MQTT_PASSWORD: &'static str = match option_env!("RP_MQTT_PASSWORD") {
-    Some(password) => password,
-    _ => "",
-};
-
 #[cfg(feature = "usb-logger")]
 async fn logger(driver: USBDriver<'static, USB>) {
     embassy_usb_logger::run!(1024, log::LevelFilter::Trace, driver);
@@ -139,14 +243,13 @@ async fn wifi_task(
 
 async fn blink_led(
     control: &mut cyw43::Control<'static>,
-    w: &mut Watchdog,
     led_on_duration: Duration,
     between_cycles_duration: Duration,
     max_cycles: u8,
 ) {
     let mut current_cycle: u8 = 0;
     loop {
-        w.feed();
+        feed_watchdog();
         control.gpio_set(0, true).await;
         Timer::after(led_on_duration).await;
         control.gpio_set(0, false).await;
@@ -158,21 +261,201 @@ async fn blink_led(
     }
 }
 
+#[embassy_executor::task]
+async fn siren_task(mut buzzer: Buzzer) -> ! {
+    buzzer.run().await
+}
+
 #[embassy_executor::task]
 async fn net_task(stack: &'static Stack<Device>) -> ! {
     stack.run().await
 }
 
+#[embassy_executor::task]
+async fn clock_task(stack: &'static Stack<Device>, server: Ipv4Address) {
+    sntp::keep_syncing(stack, server).await
+}
+
 #[embassy_executor::main]
 async fn main(spawner: Spawner) {
     let p = embassy_rp::init(Default::default());
     let fw = include_bytes!("../../embassy/cyw43-firmware/43439A0.bin");
     let clm = include_bytes!("../../embassy/cyw43-firmware/43439A0_clm.bin");
 
-    let mut watchdog = Watchdog::new(p.WATCHDOG);
+    WATCHDOG.lock(|watchdog| watchdog.replace(Some(Watchdog::new(p.WATCHDOG))));
 
     // Initialize driver.
-    let mut ultrasonic = HCSR04::new(p.PIN_2, p.PIN_3).unwrap();
+    let ultrasonic = HCSR04::new(p.PIN_2, p.PIN_3).unwrap();
+
+    let mode = Mode::from_str(config::MODE).unwrap();
+    // the second sensor sits on the inside of the corridor
+    let inner_ultrasonic = match mode {
+        Mode::Direction => Some(HCSR04::new(p.PIN_4, p.PIN_5).unwrap()),
+        _ => None,
+    };
+    let detector = Detector::from_str(config::DETECTOR).unwrap();
+    // the anomaly buckets are hours of the day, without the time it would never detect anything
+    if detector == Detector::Anomaly && config::NTP_SERVER_IP.is_empty() {
+        panic!("the anomaly detector needs RP_NTP_SERVER_IP");
+    }
+    let speeds = SpeedEstimator::new(config::VEHICLE_SPEED_MS.parse().unwrap());
+    let tamper = TamperDetector::new(
+        config::TAMPER_DISTANCE_MM.parse().unwrap(),
+        Duration::from_secs(config::TAMPER_SECS.parse().unwrap()),
+    );
+    // the tank settings only have to be valid in tank mode
+    let tank = match mode {
+        Mode::Tank => Some(
+            Tank::new(
+                config::TANK_MOUNT_HEIGHT_MM.parse().unwrap(),
+                config::TANK_EMPTY_DISTANCE_MM.parse().unwrap(),
+                config::TANK_FULL_DISTANCE_MM.parse().unwrap(),
+                Geometry::new(
+                    config::TANK_SHAPE,
+                    config::TANK_DIAMETER_MM.parse().unwrap(),
+                    config::TANK_WIDTH_MM.parse().unwrap(),
+                    config::TANK_LENGTH_MM.parse().unwrap(),
+                    config::TANK_LOOKUP,
+                )
+                .unwrap(),
+            )
+            .unwrap(),
+        ),
+        _ => None,
+    };
+    let flow = match mode {
+        Mode::Tank => Some(FlowMonitor::new(
+            Duration::from_secs(config::TANK_RATE_WINDOW_SECS.parse().unwrap()),
+            config::TANK_MAX_DRAIN_LPH.parse().unwrap(),
+            config::TANK_MAX_FILL_LPH.parse().unwrap(),
+        )),
+        _ => None,
+    };
+    let pump = match mode {
+        Mode::Tank if config::PUMP_ENABLED == "true" => Some(Pump::new(
+            Output::new(p.PIN_17.degrade(), Level::Low),
+            config::PUMP_LOW_PERCENT.parse().unwrap(),
+            config::PUMP_HIGH_PERCENT.parse().unwrap(),
+            Duration::from_secs(config::PUMP_MIN_ON_SECS.parse().unwrap()),
+            Duration::from_secs(config::PUMP_MIN_OFF_SECS.parse().unwrap()),
+            Duration::from_secs(config::PUMP_DRY_RUN_SECS.parse().unwrap()),
+            Instant::now(),
+        )),
+        _ => None,
+    };
+
+    let mut storage = Storage::new(p.FLASH);
+    let mut settings = {
+        let mut buf = [0u8; settings::MAX_LEN];
+        let stored = match storage.read(Slot::Settings, &mut buf) {
+            Ok(len) => Settings::from_json(&buf[..len]).ok(),
+            Err(_) => None,
+        };
+        stored.unwrap_or_else(|| Settings {
+            topic_prefix: String::from_str(config::TOPIC_PREFIX).unwrap(),
+            unit: DistanceUnit::Millimeters,
+            threshold_mm: 200.0,
+            intrusion_pause_ms: 100,
+            tank_interval_secs: config::TANK_INTERVAL_SECS.parse().unwrap(),
+            median_window: 1,
+            mount_offset_mm: config::MOUNT_OFFSET_MM.parse().unwrap(),
+            mount_tilt_degrees: config::MOUNT_TILT_DEGREES.parse().unwrap(),
+        })
+    };
+    let median = MedianFilter::new(settings.median_window);
+    let rules = {
+        let mut buf = [0u8; rules::MAX_LEN];
+        let stored = match storage.read(Slot::Rules, &mut buf) {
+            Ok(len) => core::str::from_utf8(&buf[..len]).ok(),
+            Err(_) => None,
+        };
+        match stored.map(Rules::from_str) {
+            Some(Ok(rules)) => rules,
+            _ => Rules::from_str(config::RULES).unwrap(),
+        }
+    };
+    debug!("rules: {}", rules.as_str());
+    // keep in sync with `OUTPUT_PINS`
+    let rule_outputs = [
+        Output::new(p.PIN_14.degrade(), Level::Low),
+        Output::new(p.PIN_15.degrade(), Level::Low),
+    ];
+    let persisted_total = {
+        let mut buf = [0u8; 4];
+        match storage.read(Slot::Counter, &mut buf) {
+            Ok(4) => u32::from_le_bytes(buf),
+            _ => 0,
+        }
+    };
+    debug!("restored passage count: {}", persisted_total);
+    let passages = PassageCounter::new(
+        config::COUNTER_BREAK_DISTANCE_MM.parse().unwrap(),
+        Duration::from_millis(config::COUNTER_MIN_GAP_MS.parse().unwrap()),
+        persisted_total,
+        Instant::now(),
+    );
+    let panel = AlarmPanel::new(
+        {
+            let mut buf = [0u8; 1];
+            match storage.read(Slot::Alarm, &mut buf) {
+                Ok(1) => ArmMode::from_byte(buf[0]).map_or(State::Disarmed, State::Armed),
+                _ => State::Disarmed,
+            }
+        },
+        Duration::from_secs(config::ALARM_EXIT_DELAY_SECS.parse().unwrap()),
+        Duration::from_secs(config::ALARM_ENTRY_DELAY_SECS.parse().unwrap()),
+        Duration::from_secs(config::ALARM_TRIGGER_SECS.parse().unwrap()),
+        Instant::now(),
+    );
+    debug!("restored alarm panel state: {}", panel.state().as_str());
+    let directions = DirectionDetector::new(config::COUNTER_BREAK_DISTANCE_MM.parse().unwrap());
+    let parking = ParkingAssistant::new(
+        config::PARKING_STOP_DISTANCE_MM.parse().unwrap(),
+        config::PARKING_APPROACH_MM.parse().unwrap(),
+        config::PARKING_TOLERANCE_MM.parse().unwrap(),
+        Duration::from_secs(config::PARKING_IDLE_SECS.parse().unwrap()),
+        Instant::now(),
+    );
+    let lights = match mode {
+        Mode::Parking if config::PARKING_EXTERNAL_LEDS == "true" => Some(Lights::new(
+            Output::new(p.PIN_6.degrade(), Level::Low),
+            Output::new(p.PIN_7.degrade(), Level::Low),
+            Output::new(p.PIN_8.degrade(), Level::Low),
+        )),
+        _ => None,
+    };
+    let scanner = match mode {
+        Mode::Scan => Some(
+            Scanner::new(
+                Servo::new(p.PWM_CH1, p.PIN_18),
+                config::SCAN_START_DEGREES.parse().unwrap(),
+                config::SCAN_END_DEGREES.parse().unwrap(),
+                config::SCAN_STEP_DEGREES.parse().unwrap(),
+                config::SCAN_THRESHOLD_MM.parse().unwrap(),
+            )
+            .unwrap(),
+        ),
+        _ => None,
+    };
+    let door = {
+        let mut buf = [0u8; 16];
+        let debounce = Duration::from_millis(config::DOOR_DEBOUNCE_MS.parse().unwrap());
+        match storage.read(Slot::Door, &mut buf) {
+            Ok(16) => Door::from_bytes(buf, debounce, Instant::now()),
+            _ => Door::new(None, None, debounce, Instant::now()),
+        }
+    };
+    let anomaly = {
+        let alpha = config::ANOMALY_ALPHA.parse().unwrap();
+        let threshold = config::ANOMALY_THRESHOLD.parse().unwrap();
+        let mut buf = [0u8; anomaly::STORED_SIZE];
+        match storage.read(Slot::Anomaly, &mut buf) {
+            Ok(anomaly::STORED_SIZE) => AnomalyDetector::from_bytes(alpha, threshold, &buf),
+            _ => AnomalyDetector::new(alpha, threshold),
+        }
+    };
+    let door_interval = Duration::from_secs(config::DOOR_INTERVAL_SECS.parse().unwrap());
+    let counter_interval = Duration::from_secs(config::COUNTER_INTERVAL_SECS.parse().unwrap());
 
     // Setup logging.
     let usb = p.USB;
@@ -181,6 +464,18 @@ async fn main(spawner: Spawner) {
 
     debug!("Pico starting up!");
 
+    if config::SIREN_ENABLED == "true" {
+        let buzzer = Buzzer::new(
+            p.PWM_CH0,
+            p.PIN_16,
+            Pattern::from_str(config::SIREN_PATTERN).unwrap(),
+            config::SIREN_LOW_HZ.parse().unwrap(),
+            config::SIREN_HIGH_HZ.parse().unwrap(),
+            Duration::from_secs(config::SIREN_SECS.parse().unwrap()),
+        );
+        unwrap!(spawner.spawn(siren_task(buzzer)));
+    }
+
     // Setup wifi
     let pwr = Output::new(p.PIN_23, Level::High);
     let cs = Output::new(p.PIN_25, Level::High);
@@ -210,14 +505,16 @@ async fn main(spawner: Spawner) {
         .set_power_management(cyw43::PowerManagementMode::Performance)
         .await;
 
-    let mac_addr: String<18> = match net_device.hardware_address() {
-        embassy_net::driver::HardwareAddress::Ethernet(addr) => mac_addr_to_str(addr),
+    let mac = match net_device.hardware_address() {
+        embassy_net::driver::HardwareAddress::Ethernet(addr) => addr,
         _ => {
             error!("failed to get mac address");
             panic!("failed to get mac address");
         }
     };
 
+    let mac_addr = mac_addr_to_str(mac);
+    let device_id = mac_addr_to_id(mac);
     debug!("mac address: {}", mac_addr);
 
     debug!("starting watchdog");
@@ -226,7 +523,6 @@ async fn main(spawner: Spawner) {
     debug!("blink 2x long blinks");
     blink_led(
         &mut control,
-        &mut watchdog,
         Duration::from_secs(2),
         Duration::from_secs(1),
         2,
@@ -237,25 +533,25 @@ async fn main(spawner: Spawner) {
 
     let seed = 0x0123_4567_89ab_cdef; // chosen by fair dice roll. guarenteed to be random.
 
-    static RESOURCES: StaticCell<StackResources<2>> = StaticCell::new();
+    // dhcp, dns, mqtt and the sntp or mdns query
+    static RESOURCES: StaticCell<StackResources<4>> = StaticCell::new();
 
     static STACK: StaticCell<Stack<cyw43::NetDriver>> = StaticCell::new();
     let stack = &*STACK.init(Stack::new(
         net_device,
         dhcp_config,
-        RESOURCES.init(StackResources::<2>::new()),
+        RESOURCES.init(StackResources::<4>::new()),
         seed,
     ));
 
     debug!("starting net task");
-    watchdog.feed();
+    feed_watchdog();
     unwrap!(spawner.spawn(net_task(&stack)));
 
     {
         debug!("blink 5x short blinks");
         blink_led(
             &mut control,
-            &mut watchdog,
             Duration::from_secs(1),
             Duration::from_millis(500),
             5,
@@ -268,10 +564,9 @@ async fn main(spawner: Spawner) {
         const MAX_RETRIES: usize = 10;
         let mut current: usize = 0;
         loop {
-            watchdog.feed();
+            feed_watchdog();
             blink_led(
                 &mut control,
-                &mut watchdog,
                 Duration::from_secs(1),
                 Duration::from_secs(1),
                 1,
@@ -295,7 +590,7 @@ async fn main(spawner: Spawner) {
         }
     }
 
-    watchdog.feed();
+    feed_watchdog();
 
     {
         debug!("wifi connected!");
@@ -306,264 +601,1169 @@ async fn main(spawner: Spawner) {
         // wait forever to get ip address
         // Wait for DHCP, not necessary when using static IP
         debug!("waiting for DHCP...");
-        let cfg = wait_for_config(stack, &mut control, &mut watchdog).await;
+        let cfg = wait_for_config(stack, &mut control).await;
 
         control.gpio_set(0, true).await;
         let local_addr = cfg.address.address();
         debug!("successfully got assigned address {} via dhcp.", local_addr);
     }
 
-    watchdog.feed();
+    if !config::NTP_SERVER_IP.is_empty() {
+        let server = Ipv4Address::from_str(config::NTP_SERVER_IP).unwrap();
+        unwrap!(spawner.spawn(clock_task(stack, server)));
+    }
+
+    feed_watchdog();
     // go into power save mode
     debug!("setting power management mode: power save");
     control
         .set_power_management(cyw43::PowerManagementMode::PowerSave)
         .await;
 
+    // subscriptions don't survive a reconnect
+    let mut commands: Vec<&str, 8> = Vec::new();
+    commands.extend_from_slice(&["rules", "siren"]).unwrap();
+    if pump.is_some() {
+        commands.push("pump").unwrap();
+    }
+    if scanner.is_some() {
+        commands.push("scan").unwrap();
+    }
+    if mode == Mode::Door {
+        commands.push("door").unwrap();
+    }
+    if mode == Mode::Intrusion {
+        commands.push("panel").unwrap();
+    }
+
+    // too large for the task arena
+    static STATION: StaticCell<Station> = StaticCell::new();
+    let station = STATION.init(Station {
+        control,
+        mode,
+        detector,
+        siren_trigger: Trigger::from_str(config::SIREN_TRIGGER).unwrap(),
+        device_id: device_id.clone(),
+        settings: settings.clone(),
+        storage,
+        ultrasonic,
+        inner_ultrasonic,
+        median,
+        tamper,
+        rules,
+        rule_outputs,
+        anomaly,
+        speeds,
+        panel,
+        tank,
+        flow,
+        pump,
+        passages,
+        persisted_total,
+        counter_interval,
+        directions,
+        parking,
+        lights,
+        scanner,
+        door,
+        door_interval,
+    });
+    debug!("starting measurement task");
+    unwrap!(spawner.spawn(measure_task(station)));
+
     let mut rx_buffer = [0; 4096];
     let mut tx_buffer = [0; 4096];
     let server_port: u16 = MQTT_SERVER_PORT.parse().unwrap();
-    let host_addr = Ipv4Address::from_str(MQTT_SERVER_IP).unwrap();
-    let addr = (host_addr, server_port);
-    debug!("got server address: {:?}", addr);
+    let mut brokers = match MQTT_BROKERS {
+        "" => Brokers::single(MQTT_SERVER, server_port, MQTT_USERNAME, MQTT_PASSWORD),
+        list => Brokers::parse(list, server_port),
+    }
+    .unwrap();
 
-    // get sensor data and send to server
-    let mut base_line: f64 = 8.0;
-    let mut counter: i8 = 0;
-    let mut buffer = ryu::Buffer::new();
+    let mut client_id: String<22> = String::new();
+    client_id.push_str("pico-").unwrap();
+    client_id.push_str(mac_addr.as_str()).unwrap();
+
+    // too large for the task arena with the `tls` feature
+    static TRANSPORT_BUFFERS: StaticCell<Buffers> = StaticCell::new();
+    let transport_buffers = TRANSPORT_BUFFERS.init_with(Buffers::default);
+    let mut recv_buffer = [0; MQTT_BUFFER_SIZE];
+    let mut write_buffer = [0; MQTT_BUFFER_SIZE];
+
+    // the mac address spreads the reconnects of several picos
+    let mut backoff = Backoff::new(
+        Duration::from_secs(1),
+        Duration::from_secs(config::MQTT_BACKOFF_MAX_SECS.parse().unwrap()),
+        byteorder::LittleEndian::read_u32(&mac[2..]),
+    );
+    let max_outage = Duration::from_secs(config::MQTT_MAX_OUTAGE_SECS.parse().unwrap());
+    // `<prefix>/<id>/cmd/time` is only waited for until measurements start
+    let mut started = false;
+
+    loop {
+        // the prefix can change at runtime, settings limit its length
+        let topics = Topics::new(&settings.topic_prefix, &device_id).unwrap();
+        // the will has to outlive the client
+        let status_topic = topics.state("status");
+
+        // dhcp might have moved the broker since the last connection
+        let broker = *brokers.current();
+        let host_addr = match resolver::resolve(stack, broker.host).await {
+            Ok(host_addr) => host_addr,
+            Err(_) => {
+                error!("failed to resolve {}", broker.host);
+                reconnect_delay(&mut brokers, &mut backoff, max_outage).await;
+                continue;
+            }
+        };
+        let addr = (host_addr, broker.port);
+        debug!("got server address: {:?}", addr);
 
-    let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
-    socket.set_keep_alive(Some(Duration::from_secs(2)));
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_keep_alive(Some(Duration::from_secs(2)));
+        // a broker that went away without closing the connection
+        socket.set_timeout(Some(Duration::from_secs(10)));
 
-    // try to connect to the server
-    for _ in 0..50 {
-        watchdog.feed();
+        // try to connect to the server
+        feed_watchdog();
         debug!("connecting...");
-        blink_led(
-            &mut control,
-            &mut watchdog,
-            Duration::from_millis(200),
-            Duration::from_millis(200),
-            1,
-        )
-        .await;
 
         if let Err(e) = socket.connect(addr).await {
             warn!("connect error: {:?}", e);
-            Timer::after_millis(200).await;
+            reconnect_delay(&mut brokers, &mut backoff, max_outage).await;
             continue;
         }
         debug!("Connected to {:?}", socket.remote_endpoint());
-        break;
+        // read from and written to at the same time
+        let socket = RefCell::new(socket);
+        let mut connection = None;
+        let (reader, writer) =
+            match transport::open(&socket, transport_buffers, &mut connection, broker.host).await {
+                Ok(halves) => halves,
+                Err(_) => {
+                    error!("failed to set up the connection to the broker");
+                    reconnect_delay(&mut brokers, &mut backoff, max_outage).await;
+                    continue;
+                }
+            };
+        let mut reader = mqtt::Reader::new(reader, &mut recv_buffer);
+        let mut writer = mqtt::Writer::new(writer, &mut write_buffer);
+
+        let mut config = mqtt::Config::new(MqttVersion::MQTTv5, CountingRng(20000));
+        config.add_client_id(client_id.as_str());
+        config.add_username(broker.username);
+        config.add_password(broker.password);
+        config.add_will(&status_topic, "offline".as_bytes(), true);
+        config.keep_alive = 43200;
+        config.max_packet_size = MQTT_BUFFER_SIZE as u32;
+
+        if writer.connect(&mut config).await.is_err() || mqtt::connected(&mut reader).await.is_err()
+        {
+            error!("failed to connect to broker");
+            reconnect_delay(&mut brokers, &mut backoff, max_outage).await;
+            continue;
+        }
+        backoff.reset();
+        brokers.connected();
+        let link = Link::new(PING_INTERVAL, Instant::now());
+
+        // everything sent from here on is answered through `mqtt::read`
+        let session = async {
+            writer.publish(&status_topic, "online".as_bytes()).await?;
+
+            announce(
+                &mut writer,
+                &topics,
+                &device_id,
+                &mac_addr,
+                mode,
+                settings.unit,
+            )
+            .await?;
+
+            if !started {
+                writer.subscribe(&topics.command("time")).await?;
+                // the measurement task may have given up on the broker already
+                match select(MEASURING.wait(), wait_for_start(&topics)).await {
+                    Either::First(()) => {
+                        debug!("already measuring, not waiting for cmd/time");
+                        started = true;
+                    }
+                    Either::Second(Start::Sleep(wait_for)) => {
+                        if writer
+                            .publish(&status_topic, "sleep".as_bytes())
+                            .await
+                            .is_err()
+                        {
+                            error!("failed to send message");
+                        }
+                        if writer.disconnect().await.is_err() {
+                            error!("failed to disconnect");
+                        }
+                        return Ok(End::Sleep(wait_for));
+                    }
+                    Either::Second(Start::Measure) => {
+                        START.signal(Start::Measure);
+                        started = true;
+                    }
+                }
+            }
+
+            for command in &commands {
+                writer.subscribe(&topics.command(command)).await?;
+            }
+            for topic in [topics.cmd(), topics.state("config/set")] {
+                writer.subscribe(&topic).await?;
+            }
+            CONNECTED.signal(());
+
+            let request = loop {
+                match mqtt::serve(&mut writer, &link, REQUEST.wait())
+                    .await
+                    .ok_or(Error)?
+                {
+                    Request::Announce(new_settings) => {
+                        debug!("announcing the new settings");
+                        settings = new_settings;
+                        announce(
+                            &mut writer,
+                            &topics,
+                            &device_id,
+                            &mac_addr,
+                            mode,
+                            settings.unit,
+                        )
+                        .await?;
+                    }
+                    request => break request,
+                }
+            };
+            if writer.disconnect().await.is_err() {
+                error!("failed to disconnect");
+            }
+            Ok::<_, Error>(End::Request(request))
+        };
+
+        let end = select(mqtt::read(&mut reader, &link), session).await;
+        transport::close(&socket);
+        match end {
+            Either::Second(Ok(End::Sleep(wait_for))) => {
+                // the measurement task owns the wifi chip
+                START.signal(Start::Sleep(wait_for));
+                return;
+            }
+            // announcements are handled within the session and never end it
+            Either::Second(Ok(End::Request(
+                Request::Reconnect(new_settings) | Request::Announce(new_settings),
+            ))) => {
+                debug!("reconnecting with the new settings");
+                settings = new_settings;
+            }
+            Either::Second(Ok(End::Request(Request::Reset))) => {
+                warn!("restarting pico on request");
+                reset();
+            }
+            Either::First(_) | Either::Second(Err(_)) => {
+                warn!("lost the connection to the broker, reconnecting");
+                reconnect_delay(&mut brokers, &mut backoff, max_outage).await;
+            }
+        }
     }
+}
 
-    // we restart the pico if we can't connect to the server
-    if socket.remote_endpoint() == None {
-        error!("failed to connect to remote server");
-        watchdog.trigger_reset();
-        return;
+#[embassy_executor::task]
+async fn measure_task(station: &'static mut Station) {
+    let mode = station.mode;
+    let detector = station.detector;
+    let siren_trigger = station.siren_trigger;
+    let device_id = station.device_id.clone();
+    let counter_interval = station.counter_interval;
+    let door_interval = station.door_interval;
+    let mut persisted_total = station.persisted_total;
+    let mut last_hour = None;
+    let Station {
+        control,
+        settings,
+        storage,
+        ultrasonic,
+        inner_ultrasonic,
+        median,
+        tamper,
+        rules,
+        rule_outputs,
+        anomaly,
+        speeds,
+        panel,
+        tank,
+        flow,
+        pump,
+        passages,
+        directions,
+        parking,
+        lights,
+        scanner,
+        door,
+        ..
+    } = station;
+
+    // without a broker nobody answers `cmd/time`, measure anyway after a while
+    let start_timeout = Duration::from_secs(config::START_TIMEOUT_SECS.parse().unwrap());
+    let start = with_timeout(start_timeout, START.wait())
+        .await
+        .unwrap_or_else(|_| {
+            warn!("no answer on cmd/time, starting measurements without the broker");
+            Start::Measure
+        });
+    match start {
+        Start::Measure => {
+            debug!("starting measurements");
+            MEASURING.signal(());
+        }
+        Start::Sleep(secs) => {
+            control.leave().await;
+            Timer::after_secs(secs).await;
+            reset();
+            return;
+        }
     }
 
-    let mut client_id: String<22> = String::new();
-    client_id.push_str("pico-").unwrap();
-    client_id.push_str(mac_addr.as_str()).unwrap();
+    // the prefix can change at runtime, settings limit its length
+    let mut topics = Topics::new(&settings.topic_prefix, &device_id).unwrap();
+    let mut base_line: f64 = INITIAL_BASE_LINE;
+    let mut counter: i8 = 0;
+    let mut tank_alert = None;
+    let mut present = false;
+    let mut measure_now = false;
+    let mut parking_signal = Signal::Dark;
+    let mut door_learn: Option<door::Command> = None;
+    let mut last_report = Instant::now();
+    // the next reading is taken once the pause of the mode is over
+    let mut resume_at = Instant::now();
 
-    let mut config = ClientConfig::new(
-        rust_mqtt::client::client_config::MqttVersion::MQTTv5,
-        CountingRng(20000),
-    );
+    loop {
+        feed_watchdog();
+        // an incoming message cuts the pause short, measure-now shouldn't have to wait it out
+        if !measure_now {
+            let incoming = poll_fn(|cx| mqtt::INCOMING.poll_ready_to_receive(cx));
+            select(Timer::at(resume_at), incoming).await;
+        }
+        // synced in the background, until then only the uptime is known
+        let clock = sntp::clock();
 
-    config.add_client_id(client_id.as_str());
-    config.add_max_subscribe_qos(rust_mqtt::packet::v5::publish_packet::QualityOfService::QoS2);
-    config.add_username(MQTT_USERNAME);
-    config.add_password(MQTT_PASSWORD);
-    config.add_will("pico-status", "offline".as_bytes(), true);
-    config.keep_alive = 43200;
-    config.max_packet_size = 100;
-
-    let mut recv_buffer = [0; 80];
-    let mut write_buffer = [0; 80];
-
-    let mut client = MqttClient::<&mut TcpSocket, 5, _>::new(
-        &mut socket,
-        &mut write_buffer,
-        80,
-        &mut recv_buffer,
-        80,
-        config,
-    );
-    client.connect_to_broker().await.unwrap();
+        if CONNECTED.signaled() {
+            CONNECTED.reset();
+            publish_state(&topics, settings, rules, mode, panel).await;
+        }
 
-    client.subscribe_to_topic("pico-time").await.unwrap();
+        // the parking mode uses the led to signal the driver
+        if mode != Mode::Parking {
+            blink_led(
+                control,
+                Duration::from_millis(100),
+                Duration::from_millis(100),
+                1,
+            )
+            .await;
+        }
 
-    match client
-        .send_message(
-            "pico-status",
-            "online".as_bytes(),
-            rust_mqtt::packet::v5::publish_packet::QualityOfService::QoS1,
-            true,
-        )
-        .await
-    {
-        Ok(_) => debug!("successfully sent message"),
-        Err(e) => error!("failed to send message: {:?}", e),
-    };
+        if let Ok(message) = mqtt::INCOMING.try_receive() {
+            let topic = message.topic;
+            let payload = core::str::from_utf8(&message.payload).unwrap_or_default();
+            match topics.command_name(&topic) {
+                // a retained reboot or factory reset would come back after every restart
+                None if topic == topics.cmd() && message.retained => {
+                    warn!("ignoring retained command: {}", payload);
+                }
+                None if topic == topics.cmd() => {
+                    let Ok(command) = command::Command::from_str(payload) else {
+                        error!("invalid command: {}", payload);
+                        acknowledge(&topics, payload.trim(), Outcome::Unknown).await;
+                        continue;
+                    };
+                    debug!("command: {:?}", command);
+
+                    let outcome = match command {
+                        // acknowledged before resetting
+                        command::Command::Reboot => Outcome::Ok,
+                        // acknowledged once the next reading has been filtered
+                        command::Command::MeasureNow => {
+                            measure_now = true;
+                            continue;
+                        }
+                        command::Command::Calibrate => match (mode, scanner.as_mut()) {
+                            (Mode::Intrusion, _) => {
+                                base_line = INITIAL_BASE_LINE;
+                                counter = 0;
+                                anomaly.reset();
+                                if let Err(e) = storage.write(Slot::Anomaly, &anomaly.to_bytes()) {
+                                    error!("failed to clear the anomaly buckets: {:?}", e);
+                                }
+                                Outcome::Ok
+                            }
+                            (Mode::Scan, Some(scanner)) => {
+                                scanner.reset_reference();
+                                Outcome::Ok
+                            }
+                            _ => Outcome::Unsupported,
+                        },
+                        command::Command::Identify => {
+                            blink_led(
+                                control,
+                                Duration::from_millis(100),
+                                Duration::from_millis(100),
+                                20,
+                            )
+                            .await;
+                            Outcome::Ok
+                        }
+                        command::Command::FactoryReset => match storage.clear() {
+                            Ok(_) => Outcome::Ok,
+                            Err(e) => {
+                                error!("failed to clear storage: {:?}", e);
+                                Outcome::Failed
+                            }
+                        },
+                    };
+                    acknowledge(&topics, command.as_str(), outcome).await;
+
+                    let resets = matches!(
+                        command,
+                        command::Command::Reboot | command::Command::FactoryReset
+                    );
+                    if resets && outcome == Outcome::Ok {
+                        // the connection sends the acknowledgement first
+                        REQUEST.signal(Request::Reset);
+                        // unless the broker is gone
+                        Timer::after_secs(10).await;
+                        reset();
+                    }
+                }
+                None if topic == topics.state("config/set") => {
+                    match settings.update(payload.as_bytes()) {
+                        Ok(new_settings) => {
+                            // topics are only subscribed to on connect, the discovery
+                            // configs can be sent again on the same connection
+                            let request = if new_settings.topic_prefix != settings.topic_prefix {
+                                Some(Request::Reconnect(new_settings.clone()))
+                            } else if new_settings.unit != settings.unit {
+                                Some(Request::Announce(new_settings.clone()))
+                            } else {
+                                None
+                            };
+                            *settings = new_settings;
+                            topics = Topics::new(&settings.topic_prefix, &device_id).unwrap();
+                            median.set_window(settings.median_window);
+
+                            match settings.to_json::<{ settings::MAX_LEN }>() {
+                                Ok(json) => {
+                                    debug!("settings updated: {}", json);
+                                    if let Err(e) = storage.write(Slot::Settings, json.as_bytes()) {
+                                        error!("failed to persist settings: {:?}", e);
+                                    }
+                                    publish(&topics.state("config"), json.as_bytes()).await;
+                                }
+                                Err(_) => error!("failed to serialize settings"),
+                            }
+                            if let Some(request) = request {
+                                REQUEST.signal(request);
+                            }
+                        }
+                        Err(_) => error!("invalid settings: {}", payload),
+                    }
+                }
+                Some("panel") if mode == Mode::Intrusion => match Command::from_str(payload) {
+                    Ok(command) => {
+                        debug!("alarm panel command: {:?}", command);
+                        if panel.command(Instant::now(), command) {
+                            on_panel_change(&topics, storage, panel, siren_trigger).await;
+                        }
+                    }
+                    Err(_) => error!("invalid alarm panel command: {}", payload),
+                },
+                Some("rules") => match Rules::from_str(payload) {
+                    Ok(new_rules) => {
+                        debug!("rules updated: {}", new_rules.as_str());
+                        *rules = new_rules;
+                        if let Err(e) = storage.write(Slot::Rules, rules.as_str().as_bytes()) {
+                            error!("failed to persist rules: {:?}", e);
+                        }
+                        publish(&topics.state("rules"), rules.as_str().as_bytes()).await;
+                    }
+                    Err(_) => error!("invalid rules: {}", payload),
+                },
+                Some("siren") => match siren::Command::from_str(payload) {
+                    Ok(command) => {
+                        debug!("siren command: {:?}", command);
+                        SIREN.signal(command);
+                    }
+                    Err(_) => error!("invalid siren command: {}", payload),
+                },
+                Some("pump") => match (pump.as_mut(), pump::Command::from_str(payload)) {
+                    (Some(pump), Ok(command)) => {
+                        debug!("pump command: {:?}", command);
+                        pump.command(command);
+                    }
+                    _ => error!("invalid pump command: {}", payload),
+                },
+                Some("scan") => match (scanner.as_mut(), payload.trim()) {
+                    (Some(scanner), "reference") => {
+                        debug!("capturing a new reference sweep");
+                        scanner.reset_reference();
+                    }
+                    _ => error!("invalid scan command: {}", payload),
+                },
+                Some("door") if mode == Mode::Door => match door::Command::from_str(payload) {
+                    Ok(command) => door_learn = Some(command),
+                    Err(_) => error!("invalid door command: {}", payload),
+                },
+                _ => debug!("ignoring message on {}", topic),
+            }
+        }
+        // back to the pause, unless the message asked for a reading
+        if !measure_now && Instant::now() < resume_at {
+            continue;
+        }
 
-    loop {
-        debug!("waiting for wait_until message");
+        if mode == Mode::Intrusion && panel.tick(Instant::now()) {
+            on_panel_change(&topics, storage, panel, siren_trigger).await;
+        }
 
-        let (topic, payload): (&str, &[u8]) = match client.receive_message().await {
-            Ok((topic, payload)) => {
-                debug!("got message: {:?} {:?}", topic, payload);
-                (topic, payload)
+        // the echo as the sensor saw it, before the mount is corrected for
+        let echo = match ultrasonic.measure().await {
+            Ok(echo) => echo,
+            Err(_) => {
+                error!("Failed to measure distance");
+                stop_pump(&topics, pump).await;
+                if core::mem::take(&mut measure_now) {
+                    acknowledge(
+                        &topics,
+                        command::Command::MeasureNow.as_str(),
+                        Outcome::Failed,
+                    )
+                    .await;
+                }
+                continue;
             }
-            Err(e) => {
-                error!("failed to receive message: {:?}", e);
-                watchdog.trigger_reset();
-                return;
+        };
+        feed_watchdog();
+
+        if let Some(tampered) = tamper.update(Instant::now(), &echo) {
+            warn!(
+                "sensor tamper state changed: {} ({:?})",
+                tampered, echo.status
+            );
+            let payload = if tampered { "tampered" } else { "ok" };
+            publish(&topics.state("tamper"), payload.as_bytes()).await;
+        }
+        // a covered sensor would otherwise look like
+        // something standing right in front of it
+        if tamper.is_blocked(&echo) {
+            stop_pump(&topics, pump).await;
+            if core::mem::take(&mut measure_now) {
+                acknowledge(
+                    &topics,
+                    command::Command::MeasureNow.as_str(),
+                    Outcome::Failed,
+                )
+                .await;
             }
+            continue;
+        }
+        let reading = settings.mount().correct(echo);
+        let unit = median.update(reading.millimeters);
+        // the same filtered reading the mode works with, whatever the mode
+        if core::mem::take(&mut measure_now) {
+            let reading = Reading::new(
+                &device_id,
+                &clock,
+                Instant::now(),
+                &reading,
+                unit,
+                settings.unit,
+            );
+            publish_reading(&topics, reading).await;
+            acknowledge(&topics, command::Command::MeasureNow.as_str(), Outcome::Ok).await;
+        }
+        for number in rules.update(Instant::now(), unit) {
+            debug!("rule {} fired at {}mm", number, unit);
+            for &action in rules.actions(number) {
+                match action {
+                    Action::Alert => {
+                        publish(
+                            &topics.state("rule"),
+                            format_count(number as u32).as_bytes(),
+                        )
+                        .await
+                    }
+                    Action::Gpio { output, high } => rule_outputs[output].set_level(high.into()),
+                }
+            }
+        }
+
+        // out of range readings jump to 4m and would look like something very fast
+        let speed = match reading.status {
+            Status::Valid => speeds.update(Instant::now(), unit),
+            _ => None,
         };
-        debug!("got message: {:?} {:?}", topic, payload);
 
-        if topic.eq("pico-time") {
-            if payload.len() == 0 {
-                debug!("got no payload. assuming no wait time, continuing...");
-                break;
-            }
-            let wait_for_str: &str = match core::str::from_utf8(payload) {
-                Ok(s) => s,
-                Err(err) => {
-                    error!(
-                        "payload is of an invalid type. expected utf-8 string. {:?}",
-                        err
-                    );
+        match mode {
+            Mode::Intrusion => {
+                let intrusion = match detector {
+                    Detector::Threshold => {
+                        if unit > base_line {
+                            feed_watchdog();
+                            base_line = unit;
+                            false
+                        } else if unit >= base_line - settings.threshold_mm {
+                            false
+                        } else {
+                            debug!("base_line has changed from {}mm to {}mm", base_line, unit);
+                            feed_watchdog();
+
+                            counter += 1;
+                            if counter > 10 {
+                                base_line = unit;
+                                counter = 0;
+                            }
+                            true
+                        }
+                    }
+                    // the buckets are hours of the day, nothing to learn without the time
+                    Detector::Anomaly => match clock.unix_ms(Instant::now()) {
+                        Some(ms) => {
+                            let hour = ((ms / 3_600_000) % 24) as usize;
+                            // save the hour that has just ended
+                            if last_hour.is_some_and(|last| last != hour) {
+                                if let Err(e) = storage.write(Slot::Anomaly, &anomaly.to_bytes()) {
+                                    error!("failed to persist the anomaly buckets: {:?}", e);
+                                }
+                            }
+                            last_hour = Some(hour);
+                            match anomaly.update(hour, unit) {
+                                Some(score) => {
+                                    debug!("anomalous reading {}mm, z-score {}", unit, score);
+                                    true
+                                }
+                                None => false,
+                            }
+                        }
+                        None => false,
+                    },
+                };
+                if intrusion != present {
+                    present = intrusion;
+                    let payload = if present { "detected" } else { "clear" };
+                    publish(&topics.state("presence"), payload.as_bytes()).await;
+                }
+                if !intrusion {
                     continue;
                 }
-            };
-            let wait_for = match wait_for_str.parse::<u64>() {
-                Ok(i) => i,
-                Err(err) => {
-                    debug!("utf-8 string is not a valid number. {:?}", err);
+
+                let reading = Reading::new(
+                    &device_id,
+                    &clock,
+                    Instant::now(),
+                    &reading,
+                    unit,
+                    settings.unit,
+                );
+                publish_reading(&topics, reading).await;
+
+                if let Some(speed) = speed {
+                    publish_measurement(
+                        &topics.state("speed"),
+                        Measurement::new(&device_id, &clock, Instant::now(), speed, "m/s"),
+                    )
+                    .await;
+                    if let Some(approach) = speeds.classify(speed) {
+                        debug!("{} approaching at {}m/s", approach.as_str(), -speed);
+                        publish(&topics.state("approach"), approach.as_str().as_bytes()).await;
+                    }
+                }
+
+                if siren_trigger == Trigger::Intrusion {
+                    SIREN.signal(siren::Command::Sound);
+                }
+                if panel.intrusion(Instant::now()) {
+                    on_panel_change(&topics, storage, panel, siren_trigger).await;
+                }
+
+                resume_at = Instant::now() + Duration::from_millis(settings.intrusion_pause_ms);
+            }
+            Mode::Tank => {
+                let (Some(tank), Some(flow)) = (tank.as_ref(), flow.as_mut()) else {
                     continue;
+                };
+                let level = tank.level(unit);
+                debug!(
+                    "tank level: {}mm {}% {}l",
+                    level.millimeters, level.percentage, level.litres
+                );
+
+                let now = Instant::now();
+                publish_measurement(
+                    &topics.state("level"),
+                    Measurement::new(&device_id, &clock, now, level.millimeters, "mm"),
+                )
+                .await;
+                publish_measurement(
+                    &topics.state("percentage"),
+                    Measurement::new(&device_id, &clock, now, level.percentage, "%"),
+                )
+                .await;
+                publish_measurement(
+                    &topics.state("litres"),
+                    Measurement::new(&device_id, &clock, now, level.litres, "L"),
+                )
+                .await;
+
+                if let Some(rate) = flow.update(now, level.litres) {
+                    publish_measurement(
+                        &topics.state("rate"),
+                        Measurement::new(&device_id, &clock, now, rate, "L/h"),
+                    )
+                    .await;
+
+                    let alert = flow.alert(rate, level.percentage);
+                    if alert != tank_alert {
+                        let payload = match alert {
+                            Some(alert) => {
+                                warn!("tank alarm: {} at {}l/h", alert.payload(), rate);
+                                alert.payload()
+                            }
+                            None => "ok",
+                        };
+                        publish(&topics.state("alarm"), payload.as_bytes()).await;
+                        tank_alert = alert;
+                    }
                 }
-            };
 
-            debug!("wait_for: {}s", wait_for);
-            if wait_for > 0 {
-                match client
-                    .send_message(
-                        "pico-status",
-                        "sleep".as_bytes(),
-                        rust_mqtt::packet::v5::publish_packet::QualityOfService::QoS1,
-                        true,
+                if reading.status != Status::Valid {
+                    stop_pump(&topics, pump).await;
+                } else if let Some(pump) = pump.as_mut() {
+                    let now = Instant::now();
+                    if pump.update(now, level.percentage, level.millimeters) {
+                        let state = pump.state();
+                        match state {
+                            pump::State::DryRun => {
+                                warn!("pump is running dry, switched off")
+                            }
+                            _ => debug!("pump switched {}", state.as_str()),
+                        }
+                        publish(&topics.state("pump"), state.as_str().as_bytes()).await;
+                    }
+                    let run_time = pump.run_time(now).as_secs() as f64;
+                    publish_measurement(
+                        &topics.state("pump-runtime"),
+                        Measurement::new(&device_id, &clock, now, run_time, "s"),
                     )
-                    .await
-                {
-                    Ok(_) => (),
-                    Err(_) => error!("failed to send message"),
+                    .await;
+                }
+
+                // tank levels change slowly, no need to flood the broker
+                resume_at = Instant::now() + Duration::from_secs(settings.tank_interval_secs);
+            }
+            Mode::Counter => {
+                let now = Instant::now();
+                if passages.update(now, unit) {
+                    debug!("passage counted, total: {}", passages.total());
+                }
+                // every passage is stored right away, a reset in between reports must not
+                // lose it. retried on the next reading when the write fails.
+                if passages.total() != persisted_total {
+                    match storage.write(Slot::Counter, &passages.total().to_le_bytes()) {
+                        Ok(_) => persisted_total = passages.total(),
+                        Err(e) => error!("failed to persist passage count: {:?}", e),
+                    }
+                }
+
+                if let Some(count) = passages.finish_hour(now) {
+                    publish_measurement(
+                        &topics.state("count-last-hour"),
+                        Measurement::new(&device_id, &clock, now, count as f64, "passages"),
+                    )
+                    .await;
+                }
+
+                if now.duration_since(last_report) < counter_interval {
+                    continue;
+                }
+                last_report = now;
+
+                let total = passages.total() as f64;
+                publish_measurement(
+                    &topics.state("count"),
+                    Measurement::new(&device_id, &clock, now, total, "passages"),
+                )
+                .await;
+                let hour_count = passages.hour_count() as f64;
+                publish_measurement(
+                    &topics.state("count-hour"),
+                    Measurement::new(&device_id, &clock, now, hour_count, "passages"),
+                )
+                .await;
+            }
+            Mode::Direction => {
+                let Some(inner_ultrasonic) = inner_ultrasonic.as_mut() else {
+                    continue;
                 };
-                match client.disconnect().await {
-                    Ok(_) => {
-                        drop(client);
-                        socket.close();
-                        drop(socket);
+                let inner = match inner_ultrasonic.measure().await {
+                    Ok(echo) => settings.mount().correct(echo).millimeters,
+                    Err(_) => {
+                        error!("Failed to measure distance on the inner sensor");
+                        continue;
                     }
-                    Err(e) => error!("failed to disconnect: {:?}", e),
                 };
+                feed_watchdog();
 
-                control.leave().await;
-                drop(control);
-                drop(ultrasonic);
+                let Some(direction) = directions.update(Instant::now(), unit, inner) else {
+                    continue;
+                };
+                debug!(
+                    "{:?} passage, occupancy: {}",
+                    direction,
+                    directions.occupancy()
+                );
+
+                let (topic, count) = match direction {
+                    Direction::Inbound => ("in", directions.inbound()),
+                    Direction::Outbound => ("out", directions.outbound()),
+                };
+                let now = Instant::now();
+                publish_measurement(
+                    &topics.state(topic),
+                    Measurement::new(&device_id, &clock, now, count as f64, "passages"),
+                )
+                .await;
+                let occupancy = directions.occupancy() as f64;
+                publish_measurement(
+                    &topics.state("occupancy"),
+                    Measurement::new(&device_id, &clock, now, occupancy, "people"),
+                )
+                .await;
+            }
+            Mode::Parking => {
+                let signal = parking.update(Instant::now(), unit);
+                if let Some(lights) = lights.as_mut() {
+                    lights.show(signal);
+                }
 
-                Timer::after_secs(wait_for).await;
-                watchdog.trigger_reset();
-                return;
-            } else {
-                // can continue sending data
-                break;
+                if signal != parking_signal {
+                    debug!("parking signal: {:?} at {}mm", signal, unit);
+                    parking_signal = signal;
+                    let distance = settings.unit.from_millimeters(unit);
+                    publish_measurement(
+                        &topics.state("parking"),
+                        Measurement::new(
+                            &device_id,
+                            &clock,
+                            Instant::now(),
+                            distance,
+                            settings.unit.as_str(),
+                        )
+                        .with_state(signal.as_str()),
+                    )
+                    .await;
+                }
+
+                // the onboard led blinks faster the closer the car gets
+                // and stays on once it has to stop
+                let (on, off) = match signal {
+                    Signal::Green => (Duration::from_millis(50), Duration::from_millis(450)),
+                    Signal::Amber => (Duration::from_millis(150), Duration::from_millis(150)),
+                    Signal::TooClose => (Duration::from_millis(50), Duration::from_millis(50)),
+                    Signal::Red => {
+                        control.gpio_set(0, true).await;
+                        resume_at = Instant::now() + Duration::from_millis(300);
+                        continue;
+                    }
+                    Signal::Dark => {
+                        control.gpio_set(0, false).await;
+                        // nothing is moving, no need to measure as often
+                        resume_at = Instant::now() + Duration::from_secs(1);
+                        continue;
+                    }
+                };
+                blink_led(control, on, off, 1).await;
+            }
+            Mode::Door => {
+                let now = Instant::now();
+                if let Some(state) = door_learn.take().map(|command| command.state()) {
+                    debug!("learned door {} at {}mm", state.as_str(), unit);
+                    door.learn(state, unit);
+                    if let Err(e) = storage.write(Slot::Door, &door.to_bytes()) {
+                        error!("failed to persist door references: {:?}", e);
+                    }
+                }
+                if !door.is_learned() {
+                    continue;
+                }
+
+                if let Some(state) = door.update(now, unit) {
+                    debug!("door is {}", state.as_str());
+                    publish(&topics.state("door"), state.as_str().as_bytes()).await;
+                }
+
+                if now.duration_since(last_report) < door_interval {
+                    continue;
+                }
+                last_report = now;
+                let open_for = door.open_for(now).as_secs() as f64;
+                publish_measurement(
+                    &topics.state("door-open-secs"),
+                    Measurement::new(&device_id, &clock, now, open_for, "s"),
+                )
+                .await;
+            }
+            Mode::Scan => {
+                let Some(scanner) = scanner.as_mut() else {
+                    continue;
+                };
+                let sweep = scanner.sweep(ultrasonic, settings.mount()).await;
+                feed_watchdog();
+                publish(
+                    &topics.state("scan"),
+                    sweep::to_csv::<_, MQTT_PAYLOAD_SIZE>(sweep.iter()).as_bytes(),
+                )
+                .await;
+
+                let changed = scanner.compare(&sweep);
+                if !changed.is_empty() {
+                    debug!("{} angles changed since the reference sweep", changed.len());
+                }
+                publish(
+                    &topics.state("scan-changes"),
+                    sweep::to_csv::<_, MQTT_PAYLOAD_SIZE>(
+                        changed.iter().map(|&index| scanner.angle(index)),
+                    )
+                    .as_bytes(),
+                )
+                .await;
             }
         }
     }
+}
 
-    let mut unit: f64;
-    let mut msg: &[u8];
+// retained state the broker may have lost while the pico was away
+async fn publish_state(
+    topics: &Topics,
+    settings: &Settings,
+    rules: &Rules,
+    mode: Mode,
+    panel: &AlarmPanel,
+) {
+    publish(&topics.state("rules"), rules.as_str().as_bytes()).await;
+    match settings.to_json::<{ settings::MAX_LEN }>() {
+        Ok(json) => publish(&topics.state("config"), json.as_bytes()).await,
+        Err(_) => error!("failed to serialize settings"),
+    }
+    if mode == Mode::Intrusion {
+        publish(&topics.state("panel"), panel.state().as_str().as_bytes()).await;
+    }
+}
 
-    loop {
-        watchdog.feed();
+// the tank level is unknown, a running pump must not keep filling blindly
+async fn stop_pump(topics: &Topics, pump: &mut Option<Pump<Output<'static, AnyPin>>>) {
+    let Some(pump) = pump.as_mut() else {
+        return;
+    };
+    if pump.stop(Instant::now()) {
+        warn!("tank level unknown, pump switched off");
+        publish(&topics.state("pump"), pump.state().as_str().as_bytes()).await;
+    }
+}
 
-        blink_led(
-            &mut control,
-            &mut watchdog,
-            Duration::from_millis(100),
-            Duration::from_millis(100),
-            1,
-        )
-        .await;
+async fn on_panel_change(
+    topics: &Topics,
+    storage: &mut Storage,
+    panel: &AlarmPanel,
+    siren_trigger: Trigger,
+) {
+    let state = panel.state();
+    debug!("alarm panel state: {}", state.as_str());
+
+    // sound before publishing, the siren has to work without a network.
+    // disarming silences it whatever sounded it.
+    match (state, siren_trigger) {
+        (State::Disarmed, _) => SIREN.signal(siren::Command::Silence),
+        (State::Triggered(_), Trigger::Panel) => SIREN.signal(siren::Command::Sound),
+        (_, Trigger::Panel) => SIREN.signal(siren::Command::Silence),
+        (_, Trigger::Intrusion) => {}
+    }
+    publish(&topics.state("panel"), state.as_str().as_bytes()).await;
+
+    // keep the pico armed when it resets
+    let mut stored = [0u8; 1];
+    let persisted = panel.persisted();
+    if !matches!(storage.read(Slot::Alarm, &mut stored), Ok(1) if stored[0] == persisted) {
+        if let Err(e) = storage.write(Slot::Alarm, &[persisted]) {
+            error!("failed to persist alarm panel state: {:?}", e);
+        }
+    }
+}
 
-        unit = match ultrasonic.measure().await {
-            Ok(unit) => unit.millimeters,
-            Err(_) => -1.0,
-        };
-        watchdog.feed();
+async fn acknowledge(topics: &Topics, command: &str, outcome: Outcome) {
+    match Ack::new(command, outcome).to_json::<MQTT_PAYLOAD_SIZE>() {
+        Ok(json) => publish(&topics.ack(), json.as_bytes()).await,
+        Err(_) => error!("failed to serialize acknowledgement"),
+    }
+}
 
-        if unit == -1.0 {
-            error!("Failed to measure distance");
-            continue;
-        }
-        if unit > base_line {
+fn feed_watchdog() {
+    WATCHDOG.lock(|watchdog| {
+        if let Some(watchdog) = watchdog.borrow_mut().as_mut() {
             watchdog.feed();
-            base_line = unit;
-            continue;
         }
-        if unit < base_line - 200.0 {
-            debug!("base_line has changed from {}mm to {}mm", base_line, unit);
-            watchdog.feed();
+    });
+}
 
-            counter += 1;
-            if counter > 10 {
-                base_line = unit;
-                counter = 0;
+// the first message on `cmd/time` tells whether to measure right away or to sleep first
+async fn wait_for_start(topics: &Topics) -> Start {
+    loop {
+        debug!("waiting for wait_until message");
+        let message = mqtt::INCOMING.receive().await;
+        debug!("got message: {:?} {:?}", message.topic, message.payload);
+        if topics.command_name(&message.topic) != Some("time") {
+            continue;
+        }
+        if message.payload.is_empty() {
+            debug!("got no payload. assuming no wait time, continuing...");
+            return Start::Measure;
+        }
+        let wait_for_str: &str = match core::str::from_utf8(&message.payload) {
+            Ok(s) => s,
+            Err(err) => {
+                error!(
+                    "payload is of an invalid type. expected utf-8 string. {:?}",
+                    err
+                );
+                continue;
             }
-            msg = buffer.format(unit).as_bytes();
-
-            let mut failed_count = 0;
-            loop {
-                match client
-                    .send_message(
-                        "pico",
-                        msg,
-                        rust_mqtt::packet::v5::publish_packet::QualityOfService::QoS1,
-                        true,
-                    )
-                    .await
-                {
-                    Err(e) => {
-                        failed_count += 1;
-                        if failed_count > 10 {
-                            error!("failed to send message more than 10 retries. Restarting pico");
-                            watchdog.trigger_reset();
-                            break;
-                        }
-                        error!("failed to send message: {:?}", e)
-                    }
-                    _ => break,
-                };
+        };
+        let wait_for = match wait_for_str.parse::<u64>() {
+            Ok(i) => i,
+            Err(err) => {
+                debug!("utf-8 string is not a valid number. {:?}", err);
+                continue;
             }
+        };
 
-            Timer::after_millis(100).await;
+        debug!("wait_for: {}s", wait_for);
+        return match wait_for {
+            0 => Start::Measure,
+            _ => Start::Sleep(wait_for),
+        };
+    }
+}
+
+fn reset() {
+    WATCHDOG.lock(|watchdog| {
+        if let Some(watchdog) = watchdog.borrow_mut().as_mut() {
+            watchdog.trigger_reset();
         }
+    });
+}
+
+// retained home assistant discovery configs, sent again when the distance unit changes
+async fn announce<W: embedded_io_async::Write>(
+    writer: &mut mqtt::Writer<'_, W>,
+    topics: &Topics,
+    device_id: &str,
+    mac_addr: &str,
+    mode: Mode,
+    unit: DistanceUnit,
+) -> Result<(), Error> {
+    if config::DISCOVERY_ENABLED != "true" {
+        return Ok(());
+    }
+    let common = [&discovery::STATUS, &discovery::HEALTH];
+    for &entity in common.iter().chain(entities(mode)) {
+        let (Ok(topic), Ok(payload)) = (
+            entity.topic(config::DISCOVERY_PREFIX, device_id),
+            entity.config::<MQTT_PAYLOAD_SIZE>(topics, device_id, mac_addr, unit.as_str()),
+        ) else {
+            error!("failed to build home assistant discovery config");
+            continue;
+        };
+        writer.publish(&topic, payload.as_bytes()).await?;
+    }
+    Ok(())
+}
+
+// what home assistant shows besides the status and health of the pico
+fn entities(mode: Mode) -> &'static [&'static Entity] {
+    match mode {
+        Mode::Intrusion => &[
+            &discovery::DISTANCE,
+            &discovery::PRESENCE,
+            &discovery::SPEED,
+        ],
+        Mode::Tank => &[
+            &discovery::LEVEL,
+            &discovery::PERCENTAGE,
+            &discovery::LITRES,
+            &discovery::RATE,
+        ],
+        Mode::Counter => &[&discovery::COUNT, &discovery::COUNT_HOUR],
+        Mode::Direction => &[
+            &discovery::INBOUND,
+            &discovery::OUTBOUND,
+            &discovery::OCCUPANCY,
+        ],
+        Mode::Parking => &[&discovery::PARKING],
+        Mode::Door => &[&discovery::DOOR, &discovery::DOOR_OPEN],
+        // sweeps are published as csv, there is no entity for them
+        Mode::Scan => &[],
+    }
+}
+
+async fn publish_reading(topics: &Topics, reading: Reading<'_>) {
+    match reading.to_json::<MQTT_PAYLOAD_SIZE>() {
+        Ok(json) => publish(&topics.state("distance"), json.as_bytes()).await,
+        Err(_) => error!("failed to serialize reading"),
+    }
+}
+
+// measurements other than the distance share one json schema
+async fn publish_measurement(topic: &str, measurement: Measurement<'_>) {
+    match measurement.to_json::<MQTT_PAYLOAD_SIZE>() {
+        Ok(json) => publish(topic, json.as_bytes()).await,
+        Err(_) => error!("failed to serialize the measurement on {}", topic),
+    }
+}
+
+fn format_count(count: u32) -> String<10> {
+    let mut msg: String<10> = String::new();
+    // u32::MAX is 10 digits long, this cannot fail
+    write!(msg, "{}", count).unwrap();
+    msg
+}
+
+// moves on to the next broker, once all of them have failed it waits before
+// the next attempt. only resets the pico once they have been unreachable for `max_outage`
+async fn reconnect_delay(brokers: &mut Brokers<'_>, backoff: &mut Backoff, max_outage: Duration) {
+    if !brokers.fail() {
+        debug!("trying the next broker: {}", brokers.current().host);
+        return;
+    }
+    let now = Instant::now();
+    if backoff.outage(now) >= max_outage {
+        error!("broker unreachable for too long. Restarting pico");
+        reset();
     }
+    let delay = backoff.next_delay(now);
+    warn!("reconnecting in {}ms", delay.as_millis());
+    Timer::after(delay).await;
 }
 
 async fn wait_for_config(
     stack: &'static Stack<Device>,
     control: &mut cyw43::Control<'static>,
-    w: &mut Watchdog,
 ) -> embassy_net::StaticConfigV4 {
     const MAX_RETRIES: usize = 20;
     let mut current: usize = 0;
     loop {
-        w.feed();
+        feed_watchdog();
         control.gpio_set(0, true).await;
         if let Some(config) = stack.config_v4() {
             break config.clone();
@@ -572,7 +1772,7 @@ async fn wait_for_config(
         control.gpio_set(0, false).await;
         if current >= MAX_RETRIES {
             error!("Failed to get IP address");
-            w.trigger_reset();
+            reset();
         }
         current += 1;
     }
@@ -0,0 +1,174 @@
+use core::fmt::Error;
+
+use heapless::Vec;
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+pub const MAX_PACKET_SIZE: usize = 512;
+const HEADER_SIZE: usize = 12;
+const TYPE_A: u16 = 1;
+
+// A standard query with a single question for the A record of `host`.
+pub fn query(host: &str, id: u16) -> Result<Vec<u8, MAX_PACKET_SIZE>> {
+    let mut query: Vec<u8, MAX_PACKET_SIZE> = Vec::new();
+    query
+        .extend_from_slice(&id.to_be_bytes())
+        .map_err(|_| Error)?;
+    query
+        .extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 0, 0, 0])
+        .map_err(|_| Error)?;
+    for label in host.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(Error);
+        }
+        query.push(label.len() as u8).map_err(|_| Error)?;
+        query
+            .extend_from_slice(label.as_bytes())
+            .map_err(|_| Error)?;
+    }
+    // end of the name, type A and class IN
+    query
+        .extend_from_slice(&[0, 0, 1, 0, 1])
+        .map_err(|_| Error)?;
+    Ok(query)
+}
+
+// The first A record in the answers of the response to query `id`. Responders
+// echo the id in unicast answers to a one-shot query, anything else on the
+// port is an answer to someone else.
+pub fn parse_answer(packet: &[u8], id: u16) -> Option<[u8; 4]> {
+    let header = packet.get(..HEADER_SIZE)?;
+    // not a response to our query
+    if read_u16(header, 0)? != id || header[2] & 0x80 == 0 {
+        return None;
+    }
+    let questions = read_u16(header, 4)?;
+    let answers = read_u16(header, 6)?;
+
+    let mut offset = HEADER_SIZE;
+    for _ in 0..questions {
+        // followed by the type and class
+        offset = skip_name(packet, offset)? + 4;
+    }
+    for _ in 0..answers {
+        offset = skip_name(packet, offset)?;
+        // type, class, ttl and the length of the data
+        let record = packet.get(offset..offset + 10)?;
+        let kind = read_u16(record, 0)?;
+        let len = read_u16(record, 8)? as usize;
+        let data = packet.get(offset + 10..offset + 10 + len)?;
+        if kind == TYPE_A && len == 4 {
+            return data.try_into().ok();
+        }
+        offset += 10 + len;
+    }
+    None
+}
+
+// returns the offset right after the name
+fn skip_name(packet: &[u8], mut offset: usize) -> Option<usize> {
+    loop {
+        let len = *packet.get(offset)?;
+        match len {
+            0 => return Some(offset + 1),
+            // a pointer to a name earlier in the packet ends it
+            len if len & 0xc0 == 0xc0 => return Some(offset + 2),
+            len => offset += 1 + len as usize,
+        }
+    }
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    let bytes = bytes.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: u16 = 0x1234;
+
+    // the question for pico-broker.local
+    const QUESTION: &[u8] = b"\x0bpico-broker\x05local\x00\x00\x01\x00\x01";
+
+    fn response(id: u16, answers: &[&[u8]]) -> std::vec::Vec<u8> {
+        let mut packet = id.to_be_bytes().to_vec();
+        packet.extend_from_slice(&[0x84, 0, 0, 1, 0, answers.len() as u8, 0, 0, 0, 0]);
+        packet.extend_from_slice(QUESTION);
+        for answer in answers {
+            packet.extend_from_slice(answer);
+        }
+        packet
+    }
+
+    // a record for the name at the given pointer
+    fn record(pointer: u8, kind: u8, data: &[u8]) -> std::vec::Vec<u8> {
+        let mut record = vec![
+            0xc0,
+            pointer,
+            0,
+            kind,
+            0,
+            1,
+            0,
+            0,
+            0,
+            120,
+            0,
+            data.len() as u8,
+        ];
+        record.extend_from_slice(data);
+        record
+    }
+
+    #[test]
+    fn query_asks_for_the_a_record() {
+        let query = query("pico-broker.local.", ID).unwrap();
+        assert_eq!(&query[..2], &[0x12, 0x34]);
+        assert_eq!(&query[12..], QUESTION);
+        assert!(super::query("pico..local", ID).is_err());
+    }
+
+    #[test]
+    fn follows_compressed_names() {
+        // points back at the name in the question
+        let packet = response(ID, &[&record(12, 1, &[192, 168, 1, 10])]);
+        assert_eq!(parse_answer(&packet, ID), Some([192, 168, 1, 10]));
+    }
+
+    #[test]
+    fn skips_other_records() {
+        let ipv6 = record(
+            12,
+            28,
+            &[0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+        );
+        let mut full_name = QUESTION[..QUESTION.len() - 4].to_vec();
+        full_name.extend_from_slice(&[0, 1, 0, 1, 0, 0, 0, 120, 0, 4, 10, 0, 0, 2]);
+        let packet = response(ID, &[&ipv6, &full_name, &record(12, 1, &[10, 0, 0, 3])]);
+        assert_eq!(parse_answer(&packet, ID), Some([10, 0, 0, 2]));
+
+        let packet = response(ID, &[&ipv6]);
+        assert_eq!(parse_answer(&packet, ID), None);
+    }
+
+    #[test]
+    fn rejects_truncated_packets() {
+        let packet = response(ID, &[&record(12, 1, &[192, 168, 1, 10])]);
+        for len in 0..packet.len() {
+            assert_eq!(parse_answer(&packet[..len], ID), None, "{}", len);
+        }
+    }
+
+    #[test]
+    fn ignores_other_queries_and_questions() {
+        let answer = record(12, 1, &[192, 168, 1, 10]);
+        assert_eq!(parse_answer(&response(ID + 1, &[&answer]), ID), None);
+
+        let mut question = response(ID, &[&answer]);
+        // clears the response flag
+        question[2] = 0;
+        assert_eq!(parse_answer(&question, ID), None);
+    }
+}
@@ -0,0 +1,433 @@
+use core::cell::RefCell;
+use core::fmt::Error;
+use core::future::Future;
+use core::pin::pin;
+use core::str::FromStr;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use embassy_futures::select::{select4, Either4};
+use embassy_sync::blocking_mutex::raw::{CriticalSectionRawMutex, NoopRawMutex};
+use embassy_sync::channel::{Channel, TrySendError};
+use embassy_time::{Duration, Instant, Timer};
+use embedded_io_async::{Read, Write};
+use heapless::{String, Vec};
+use log::{debug, error, warn};
+use rust_mqtt::client::client_config::ClientConfig;
+use rust_mqtt::packet::v5::connack_packet::ConnackPacket;
+use rust_mqtt::packet::v5::connect_packet::ConnectPacket;
+use rust_mqtt::packet::v5::disconnect_packet::DisconnectPacket;
+use rust_mqtt::packet::v5::mqtt_packet::Packet;
+use rust_mqtt::packet::v5::packet_type::PacketType;
+use rust_mqtt::packet::v5::pingreq_packet::PingreqPacket;
+use rust_mqtt::packet::v5::puback_packet::PubackPacket;
+use rust_mqtt::packet::v5::publish_packet::{PublishPacket, QualityOfService};
+use rust_mqtt::packet::v5::suback_packet::SubackPacket;
+use rust_mqtt::packet::v5::subscription_packet::SubscriptionPacket;
+use rust_mqtt::utils::buffer_reader::BuffReader;
+use rust_mqtt::utils::rng_generator::CountingRng;
+
+use crate::connection::Session;
+use crate::topic::Topic;
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+pub const MAX_PROPERTIES: usize = 5;
+
+pub type Config<'a> = ClientConfig<'a, MAX_PROPERTIES, CountingRng>;
+
+// large enough for rules, settings and home assistant discovery configs
+pub const MQTT_BUFFER_SIZE: usize = 512;
+// leaves room for the topic and packet headers
+pub const MQTT_PAYLOAD_SIZE: usize = 448;
+
+// filled by the measurement loop, emptied by `serve`.
+// whatever is left over is sent after the next reconnect
+pub static OUTGOING: Channel<CriticalSectionRawMutex, Message, 8> = Channel::new();
+// filled by `read`, emptied by the measurement loop
+pub static INCOMING: Channel<CriticalSectionRawMutex, Message, 4> = Channel::new();
+// whether `serve` is emptying `OUTGOING`
+static ONLINE: AtomicBool = AtomicBool::new(false);
+
+pub struct Message {
+    pub topic: Topic,
+    pub payload: Vec<u8, MQTT_PAYLOAD_SIZE>,
+    // stored by the broker and sent again on every subscribe
+    pub retained: bool,
+}
+
+impl Message {
+    pub fn new(topic: &str, payload: &[u8]) -> Result<Self> {
+        Ok(Self {
+            topic: String::from_str(topic).map_err(|_| Error)?,
+            payload: Vec::from_slice(payload).map_err(|_| Error)?,
+            retained: false,
+        })
+    }
+}
+
+// `serve` may be dropped halfway, this outlives it
+struct Online;
+
+impl Online {
+    fn new() -> Self {
+        ONLINE.store(true, Ordering::Relaxed);
+        Self
+    }
+}
+
+impl Drop for Online {
+    fn drop(&mut self) {
+        ONLINE.store(false, Ordering::Relaxed);
+    }
+}
+
+// What `read` and `serve` share for one connection. `read` marks the session
+// active and leaves the identifiers of received messages for `serve` to ack.
+pub struct Link {
+    session: RefCell<Session>,
+    acks: Channel<NoopRawMutex, u16, 4>,
+}
+
+impl Link {
+    pub fn new(ping_interval: Duration, now: Instant) -> Self {
+        Self {
+            session: RefCell::new(Session::new(ping_interval, now)),
+            acks: Channel::new(),
+        }
+    }
+}
+
+pub enum Received<'a> {
+    Connack(u8),
+    Publish {
+        topic: &'a str,
+        payload: &'a [u8],
+        // only set for messages the broker wants an ack for
+        identifier: Option<u16>,
+        retained: bool,
+    },
+    // the broker refused a subscription
+    Refused,
+    Disconnect(u8),
+    // acks and ping responses
+    Other,
+}
+
+// The receiving half of the connection, one packet at a time.
+pub struct Reader<'a, R> {
+    transport: R,
+    buffer: &'a mut [u8],
+}
+
+impl<'a, R: Read> Reader<'a, R> {
+    pub fn new(transport: R, buffer: &'a mut [u8]) -> Self {
+        Self { transport, buffer }
+    }
+
+    async fn read_exact(&mut self, start: usize, end: usize) -> Result<()> {
+        self.transport
+            .read_exact(&mut self.buffer[start..end])
+            .await
+            .map_err(|_| Error)
+    }
+
+    // not cancel safe, a packet cut off halfway leaves the rest of it on the connection
+    pub async fn next(&mut self) -> Result<Received<'_>> {
+        // the packet type and up to four bytes of remaining length
+        self.read_exact(0, 1).await?;
+        let mut len = 0;
+        let mut header = 1;
+        loop {
+            if header == 5 {
+                return Err(Error);
+            }
+            self.read_exact(header, header + 1).await?;
+            let byte = self.buffer[header];
+            len |= ((byte & 0x7f) as usize) << (7 * (header - 1));
+            header += 1;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        if header + len > self.buffer.len() {
+            error!("packet of {} bytes is too large", header + len);
+            return Err(Error);
+        }
+        self.read_exact(header, header + len).await?;
+        decode(&self.buffer[..header + len])
+    }
+}
+
+fn decode(packet: &[u8]) -> Result<Received<'_>> {
+    let mut reader = BuffReader::new(packet, packet.len());
+    match PacketType::from(packet[0]) {
+        PacketType::Connack => {
+            let mut connack = ConnackPacket::<MAX_PROPERTIES>::new();
+            connack.decode(&mut reader).map_err(|_| Error)?;
+            Ok(Received::Connack(connack.connect_reason_code))
+        }
+        PacketType::Publish => {
+            let mut publish = PublishPacket::<MAX_PROPERTIES>::new();
+            publish.decode(&mut reader).map_err(|_| Error)?;
+            let qos = (publish.fixed_header & 0x06) >> 1;
+            Ok(Received::Publish {
+                topic: publish.topic_name.string,
+                payload: publish.message.unwrap_or(&[]),
+                identifier: (qos > 0).then_some(publish.packet_identifier),
+                retained: publish.fixed_header & 0x01 != 0,
+            })
+        }
+        PacketType::Suback => {
+            let mut suback = SubackPacket::<1, MAX_PROPERTIES>::new();
+            suback.decode(&mut reader).map_err(|_| Error)?;
+            // below 0x80 is the granted quality of service
+            match suback.reason_codes.iter().any(|&code| code >= 0x80) {
+                true => Ok(Received::Refused),
+                false => Ok(Received::Other),
+            }
+        }
+        PacketType::Disconnect => {
+            let mut disconnect = DisconnectPacket::<MAX_PROPERTIES>::new();
+            disconnect.decode(&mut reader).map_err(|_| Error)?;
+            Ok(Received::Disconnect(disconnect.disconnect_reason))
+        }
+        PacketType::Puback | PacketType::Pingresp => Ok(Received::Other),
+        _ => Err(Error),
+    }
+}
+
+// The sending half of the connection. Nothing here waits for the broker to answer,
+// the answers come in through the `Reader`.
+pub struct Writer<'a, W> {
+    transport: W,
+    buffer: &'a mut [u8],
+    identifier: u16,
+}
+
+impl<'a, W: Write> Writer<'a, W> {
+    pub fn new(transport: W, buffer: &'a mut [u8]) -> Self {
+        Self {
+            transport,
+            buffer,
+            identifier: 0,
+        }
+    }
+
+    // zero is not a valid packet identifier
+    fn next_identifier(&mut self) -> u16 {
+        self.identifier = self.identifier.checked_add(1).unwrap_or(1);
+        self.identifier
+    }
+
+    async fn write(&mut self, len: usize) -> Result<()> {
+        self.transport
+            .write_all(&self.buffer[..len])
+            .await
+            .map_err(|_| Error)?;
+        self.transport.flush().await.map_err(|_| Error)
+    }
+
+    pub async fn connect(&mut self, config: &mut Config<'_>) -> Result<()> {
+        let mut connect = ConnectPacket::<MAX_PROPERTIES, 0>::new();
+        connect.keep_alive = config.keep_alive;
+        config.add_max_packet_size_as_prop();
+        connect.property_len = connect.add_properties(&config.properties);
+        if config.username_flag {
+            connect.add_username(&config.username);
+        }
+        if config.password_flag {
+            connect.add_password(&config.password);
+        }
+        if config.will_flag {
+            connect.add_will(&config.will_topic, &config.will_payload, config.will_retain);
+        }
+        connect.add_client_id(&config.client_id);
+        let len = connect
+            .encode(self.buffer, self.buffer.len())
+            .map_err(|_| Error)?;
+        self.write(len).await
+    }
+
+    // retained and at least once
+    pub async fn publish(&mut self, topic: &str, payload: &[u8]) -> Result<()> {
+        let identifier = self.next_identifier();
+        let mut publish = PublishPacket::<MAX_PROPERTIES>::new();
+        publish.add_topic_name(topic);
+        publish.add_qos(QualityOfService::QoS1);
+        publish.add_identifier(identifier);
+        publish.add_message(payload);
+        publish.add_retain(true);
+        let len = publish
+            .encode(self.buffer, self.buffer.len())
+            .map_err(|_| Error)?;
+        self.write(len).await
+    }
+
+    pub async fn subscribe(&mut self, topic: &str) -> Result<()> {
+        let mut subscribe = SubscriptionPacket::<1, MAX_PROPERTIES>::new();
+        subscribe.packet_identifier = self.next_identifier();
+        subscribe.add_new_filter(topic, QualityOfService::QoS1);
+        let len = subscribe
+            .encode(self.buffer, self.buffer.len())
+            .map_err(|_| Error)?;
+        self.write(len).await
+    }
+
+    async fn ack(&mut self, identifier: u16) -> Result<()> {
+        let mut puback = PubackPacket::<MAX_PROPERTIES>::new();
+        puback.packet_identifier = identifier;
+        puback.reason_code = 0x00;
+        let len = puback
+            .encode(self.buffer, self.buffer.len())
+            .map_err(|_| Error)?;
+        self.write(len).await
+    }
+
+    async fn ping(&mut self) -> Result<()> {
+        let len = PingreqPacket::new()
+            .encode(self.buffer, self.buffer.len())
+            .map_err(|_| Error)?;
+        self.write(len).await
+    }
+
+    pub async fn disconnect(&mut self) -> Result<()> {
+        let len = DisconnectPacket::<MAX_PROPERTIES>::new()
+            .encode(self.buffer, self.buffer.len())
+            .map_err(|_| Error)?;
+        self.write(len).await
+    }
+}
+
+// waits for the broker to accept the connection, before anything else is read
+pub async fn connected<R: Read>(reader: &mut Reader<'_, R>) -> Result<()> {
+    match reader.next().await? {
+        Received::Connack(0) => Ok(()),
+        Received::Connack(reason) => {
+            error!("broker refused the connection: {}", reason);
+            Err(Error)
+        }
+        _ => Err(Error),
+    }
+}
+
+// queues a message for `serve`, waits while the queue is full and `serve` is emptying it
+pub async fn publish(topic: &str, payload: &[u8]) {
+    let Ok(message) = Message::new(topic, payload) else {
+        error!("message on {} is too large, dropping it", topic);
+        return;
+    };
+    if ONLINE.load(Ordering::Relaxed) {
+        OUTGOING.send(message).await;
+        return;
+    }
+    // the measurements go on while the broker is away, the oldest message gives way
+    if let Err(TrySendError::Full(message)) = OUTGOING.try_send(message) {
+        debug!("broker unreachable, dropping the oldest message");
+        let _ = OUTGOING.try_receive();
+        let _ = OUTGOING.try_send(message);
+    }
+}
+
+// The only place reading from the broker, it runs for as long as the connection and
+// is never cut short. Hands received messages to the measurement loop and returns
+// once the connection is broken.
+pub async fn read<R: Read>(reader: &mut Reader<'_, R>, link: &Link) {
+    loop {
+        let received = match reader.next().await {
+            Ok(received) => received,
+            Err(_) => {
+                error!("failed to receive from the broker");
+                break;
+            }
+        };
+        link.session.borrow_mut().active(Instant::now());
+        match received {
+            Received::Publish {
+                topic,
+                payload,
+                identifier,
+                retained,
+            } => {
+                // the broker sends it again if the ack gets lost
+                if let Some(identifier) = identifier {
+                    let _ = link.acks.try_send(identifier);
+                }
+                match Message::new(topic, payload) {
+                    Ok(mut message) => {
+                        message.retained = retained;
+                        // a stalled measurement loop must not stall the connection
+                        if INCOMING.try_send(message).is_err() {
+                            error!("too many messages waiting, dropping one on {}", topic);
+                        }
+                    }
+                    Err(_) => debug!("ignoring message on {}", topic),
+                }
+            }
+            Received::Refused => {
+                error!("broker refused a subscription");
+                break;
+            }
+            Received::Disconnect(reason) => {
+                warn!("broker closed the session: {}", reason);
+                break;
+            }
+            Received::Connack(_) | Received::Other => (),
+        }
+    }
+    link.session.borrow_mut().fail();
+}
+
+// Sends queued messages, acks received ones and pings a quiet broker until `stop`
+// resolves or the session breaks. Only the waiting is raced against `stop`, a packet
+// is never cut off halfway. Whatever is still queued when `stop` resolves is sent
+// before returning its output.
+pub async fn serve<W: Write, T>(
+    writer: &mut Writer<'_, W>,
+    link: &Link,
+    stop: impl Future<Output = T>,
+) -> Option<T> {
+    let _online = Online::new();
+    let mut stop = pin!(stop);
+    while !link.session.borrow().is_broken() {
+        let ping_at = link.session.borrow().ping_at();
+        let sent = match select4(
+            OUTGOING.receive(),
+            link.acks.receive(),
+            Timer::at(ping_at),
+            &mut stop,
+        )
+        .await
+        {
+            Either4::First(message) => writer.publish(&message.topic, &message.payload).await,
+            Either4::Second(identifier) => writer.ack(identifier).await,
+            Either4::Third(_) => writer.ping().await,
+            Either4::Fourth(output) => {
+                flush(writer, link).await;
+                return Some(output);
+            }
+        };
+        let mut session = link.session.borrow_mut();
+        match sent {
+            Ok(_) => session.active(Instant::now()),
+            Err(_) => {
+                error!("failed to send to the broker");
+                session.fail();
+            }
+        }
+    }
+    None
+}
+
+// sends what is still queued before the connection is dropped on purpose
+async fn flush<W: Write>(writer: &mut Writer<'_, W>, link: &Link) {
+    while let Ok(message) = OUTGOING.try_receive() {
+        if writer
+            .publish(&message.topic, &message.payload)
+            .await
+            .is_err()
+        {
+            link.session.borrow_mut().fail();
+            break;
+        }
+    }
+}
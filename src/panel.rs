@@ -0,0 +1,267 @@
+use core::str::FromStr;
+
+use embassy_time::{Duration, Instant};
+
+// States and commands follow the Home Assistant MQTT alarm_control_panel
+// payloads, https://www.home-assistant.io/integrations/alarm_control_panel.mqtt/
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ArmMode {
+    Home = 1,
+    Away = 2,
+    Night = 3,
+}
+
+impl ArmMode {
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            1 => Some(ArmMode::Home),
+            2 => Some(ArmMode::Away),
+            3 => Some(ArmMode::Night),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum State {
+    Disarmed,
+    // exit delay
+    Arming(ArmMode),
+    Armed(ArmMode),
+    // entry delay
+    Pending(ArmMode),
+    // the mode to return to, none when triggered while disarmed
+    Triggered(Option<ArmMode>),
+}
+
+impl State {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            State::Disarmed => "disarmed",
+            State::Arming(_) => "arming",
+            State::Armed(ArmMode::Home) => "armed_home",
+            State::Armed(ArmMode::Away) => "armed_away",
+            State::Armed(ArmMode::Night) => "armed_night",
+            State::Pending(_) => "pending",
+            State::Triggered(_) => "triggered",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Command {
+    Disarm,
+    Arm(ArmMode),
+    Trigger,
+}
+
+impl FromStr for Command {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "DISARM" => Ok(Command::Disarm),
+            "ARM_HOME" => Ok(Command::Arm(ArmMode::Home)),
+            "ARM_AWAY" => Ok(Command::Arm(ArmMode::Away)),
+            "ARM_NIGHT" => Ok(Command::Arm(ArmMode::Night)),
+            "TRIGGER" => Ok(Command::Trigger),
+            _ => Err(()),
+        }
+    }
+}
+
+pub struct AlarmPanel {
+    state: State,
+    since: Instant,
+    // time to leave after arming
+    exit_delay: Duration,
+    // time to disarm after an intrusion
+    entry_delay: Duration,
+    // how long to stay triggered before re-arming, zero stays triggered until disarmed
+    trigger_time: Duration,
+}
+
+impl AlarmPanel {
+    pub fn new(
+        state: State,
+        exit_delay: Duration,
+        entry_delay: Duration,
+        trigger_time: Duration,
+        now: Instant,
+    ) -> Self {
+        Self {
+            state,
+            since: now,
+            exit_delay,
+            entry_delay,
+            trigger_time,
+        }
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    // the state to restore after a reset, only settled states are kept
+    pub fn persisted(&self) -> u8 {
+        match self.state {
+            State::Disarmed | State::Arming(_) | State::Triggered(None) => 0,
+            State::Armed(mode) | State::Pending(mode) | State::Triggered(Some(mode)) => mode as u8,
+        }
+    }
+
+    // all of the functions below return true when the state has changed
+
+    pub fn command(&mut self, now: Instant, command: Command) -> bool {
+        let state = match command {
+            Command::Disarm => State::Disarmed,
+            Command::Arm(mode) if self.exit_delay.as_ticks() == 0 => State::Armed(mode),
+            Command::Arm(mode) => State::Arming(mode),
+            Command::Trigger => State::Triggered(self.mode()),
+        };
+
+        self.set(now, state)
+    }
+
+    pub fn intrusion(&mut self, now: Instant) -> bool {
+        match self.state {
+            State::Armed(mode) if self.entry_delay.as_ticks() == 0 => {
+                self.set(now, State::Triggered(Some(mode)))
+            }
+            State::Armed(mode) => self.set(now, State::Pending(mode)),
+            _ => false,
+        }
+    }
+
+    // moves on from the timed states once their delay has passed
+    pub fn tick(&mut self, now: Instant) -> bool {
+        let elapsed = now.duration_since(self.since);
+        match self.state {
+            State::Arming(mode) if elapsed >= self.exit_delay => self.set(now, State::Armed(mode)),
+            State::Pending(mode) if elapsed >= self.entry_delay => {
+                self.set(now, State::Triggered(Some(mode)))
+            }
+            State::Triggered(mode)
+                if self.trigger_time.as_ticks() > 0 && elapsed >= self.trigger_time =>
+            {
+                self.set(now, mode.map_or(State::Disarmed, State::Armed))
+            }
+            _ => false,
+        }
+    }
+
+    fn mode(&self) -> Option<ArmMode> {
+        match self.state {
+            State::Disarmed => None,
+            State::Arming(mode) | State::Armed(mode) | State::Pending(mode) => Some(mode),
+            State::Triggered(mode) => mode,
+        }
+    }
+
+    fn set(&mut self, now: Instant, state: State) -> bool {
+        if state == self.state {
+            return false;
+        }
+
+        self.state = state;
+        self.since = now;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn panel(exit: u64, entry: u64, trigger: u64) -> AlarmPanel {
+        AlarmPanel::new(
+            State::Disarmed,
+            Duration::from_secs(exit),
+            Duration::from_secs(entry),
+            Duration::from_secs(trigger),
+            Instant::from_secs(0),
+        )
+    }
+
+    #[test]
+    fn arms_after_the_exit_delay() {
+        let mut panel = panel(30, 0, 0);
+        assert!(panel.command(Instant::from_secs(0), Command::Arm(ArmMode::Away)));
+        assert_eq!(panel.state(), State::Arming(ArmMode::Away));
+        assert_eq!(panel.persisted(), 0);
+        // nothing to trigger while leaving
+        assert!(!panel.intrusion(Instant::from_secs(10)));
+
+        assert!(!panel.tick(Instant::from_secs(29)));
+        assert!(panel.tick(Instant::from_secs(30)));
+        assert_eq!(panel.state(), State::Armed(ArmMode::Away));
+        assert_eq!(panel.persisted(), ArmMode::Away as u8);
+    }
+
+    #[test]
+    fn triggers_after_the_entry_delay_unless_disarmed() {
+        let mut panel = panel(0, 20, 0);
+        panel.command(Instant::from_secs(0), Command::Arm(ArmMode::Night));
+        assert_eq!(panel.state(), State::Armed(ArmMode::Night));
+
+        assert!(panel.intrusion(Instant::from_secs(100)));
+        assert_eq!(panel.state(), State::Pending(ArmMode::Night));
+        assert!(!panel.tick(Instant::from_secs(119)));
+        assert!(panel.tick(Instant::from_secs(120)));
+        assert_eq!(panel.state(), State::Triggered(Some(ArmMode::Night)));
+
+        panel.command(Instant::from_secs(130), Command::Arm(ArmMode::Night));
+        panel.intrusion(Instant::from_secs(140));
+        assert!(panel.command(Instant::from_secs(150), Command::Disarm));
+        assert!(!panel.tick(Instant::from_secs(200)));
+        assert_eq!(panel.state(), State::Disarmed);
+    }
+
+    #[test]
+    fn rearms_after_the_trigger_time() {
+        let mut panel = panel(0, 0, 60);
+        panel.command(Instant::from_secs(0), Command::Arm(ArmMode::Home));
+        assert!(panel.intrusion(Instant::from_secs(10)));
+        assert_eq!(panel.state(), State::Triggered(Some(ArmMode::Home)));
+        assert_eq!(panel.persisted(), ArmMode::Home as u8);
+
+        assert!(!panel.tick(Instant::from_secs(69)));
+        assert!(panel.tick(Instant::from_secs(70)));
+        assert_eq!(panel.state(), State::Armed(ArmMode::Home));
+    }
+
+    #[test]
+    fn stays_triggered_without_a_trigger_time() {
+        let mut panel = panel(0, 0, 0);
+        panel.command(Instant::from_secs(0), Command::Arm(ArmMode::Away));
+        panel.intrusion(Instant::from_secs(10));
+        assert!(!panel.tick(Instant::from_secs(100_000)));
+        assert_eq!(panel.state(), State::Triggered(Some(ArmMode::Away)));
+    }
+
+    #[test]
+    fn manual_trigger_while_disarmed_returns_to_disarmed() {
+        let mut panel = panel(0, 0, 60);
+        assert!(panel.command(Instant::from_secs(0), Command::Trigger));
+        assert_eq!(panel.state(), State::Triggered(None));
+        assert_eq!(panel.state().as_str(), "triggered");
+        assert_eq!(panel.persisted(), 0);
+
+        assert!(panel.tick(Instant::from_secs(60)));
+        assert_eq!(panel.state(), State::Disarmed);
+        assert_eq!(panel.persisted(), 0);
+    }
+
+    #[test]
+    fn parses_the_home_assistant_commands() {
+        assert_eq!(
+            Command::from_str("ARM_AWAY\n"),
+            Ok(Command::Arm(ArmMode::Away))
+        );
+        assert_eq!(Command::from_str("DISARM"), Ok(Command::Disarm));
+        assert!(Command::from_str("arm_away").is_err());
+        assert_eq!(ArmMode::from_byte(3), Some(ArmMode::Night));
+        assert_eq!(ArmMode::from_byte(0), None);
+    }
+}
@@ -0,0 +1,160 @@
+use embassy_time::{Duration, Instant};
+use embedded_hal::digital::OutputPin;
+
+// movements smaller than this are sensor noise, not the car moving
+const STILL_TOLERANCE: f64 = 30.0;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Signal {
+    // keep driving
+    Green,
+    // getting close to the stop distance
+    Amber,
+    // stop
+    Red,
+    // past the stop distance, reverse
+    TooClose,
+    // nothing has moved for a while
+    Dark,
+}
+
+impl Signal {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Signal::Green => "green",
+            Signal::Amber => "amber",
+            Signal::Red => "red",
+            Signal::TooClose => "too-close",
+            Signal::Dark => "dark",
+        }
+    }
+}
+
+pub struct ParkingAssistant {
+    stop_distance: f64,
+    // amber is shown within this range before the stop distance
+    approach: f64,
+    // red is shown within this range past the stop distance
+    tolerance: f64,
+    idle: Duration,
+    still_distance: f64,
+    still_since: Instant,
+}
+
+impl ParkingAssistant {
+    pub fn new(
+        stop_distance: f64,
+        approach: f64,
+        tolerance: f64,
+        idle: Duration,
+        now: Instant,
+    ) -> Self {
+        Self {
+            stop_distance,
+            approach,
+            tolerance,
+            idle,
+            still_distance: 0.0,
+            still_since: now,
+        }
+    }
+
+    pub fn update(&mut self, now: Instant, distance: f64) -> Signal {
+        if libm::fabs(distance - self.still_distance) > STILL_TOLERANCE {
+            self.still_distance = distance;
+            self.still_since = now;
+        }
+        if now.duration_since(self.still_since) >= self.idle {
+            return Signal::Dark;
+        }
+
+        if distance < self.stop_distance - self.tolerance {
+            Signal::TooClose
+        } else if distance <= self.stop_distance {
+            Signal::Red
+        } else if distance <= self.stop_distance + self.approach {
+            Signal::Amber
+        } else {
+            Signal::Green
+        }
+    }
+}
+
+// optional external traffic light LEDs
+pub struct Lights<P> {
+    green: P,
+    amber: P,
+    red: P,
+}
+
+impl<P: OutputPin> Lights<P> {
+    pub fn new(green: P, amber: P, red: P) -> Self {
+        let mut lights = Self { green, amber, red };
+        lights.show(Signal::Dark);
+        lights
+    }
+
+    pub fn show(&mut self, signal: Signal) {
+        let (green, amber, red) = match signal {
+            Signal::Green => (true, false, false),
+            Signal::Amber => (false, true, false),
+            Signal::Red => (false, false, true),
+            Signal::TooClose => (false, true, true),
+            Signal::Dark => (false, false, false),
+        };
+        let _ = self.green.set_state(green.into());
+        let _ = self.amber.set_state(amber.into());
+        let _ = self.red.set_state(red.into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assistant() -> ParkingAssistant {
+        // stop at 500mm, amber from 1000mm, red down to 400mm, dark after a minute
+        ParkingAssistant::new(
+            500.0,
+            500.0,
+            100.0,
+            Duration::from_secs(60),
+            Instant::from_secs(0),
+        )
+    }
+
+    #[test]
+    fn signals_by_distance_to_the_stop() {
+        let mut parking = assistant();
+        for (second, distance, signal) in [
+            (1, 2000.0, Signal::Green),
+            (2, 1000.1, Signal::Green),
+            (3, 1000.0, Signal::Amber),
+            (4, 500.1, Signal::Amber),
+            (5, 500.0, Signal::Red),
+            (6, 400.0, Signal::Red),
+            (7, 399.9, Signal::TooClose),
+        ] {
+            assert_eq!(
+                parking.update(Instant::from_secs(second), distance),
+                signal,
+                "{}",
+                distance
+            );
+        }
+    }
+
+    #[test]
+    fn goes_dark_once_nothing_moves() {
+        let mut parking = assistant();
+        assert_eq!(parking.update(Instant::from_secs(0), 480.0), Signal::Red);
+        // noise is not movement
+        assert_eq!(parking.update(Instant::from_secs(59), 470.0), Signal::Red);
+        assert_eq!(parking.update(Instant::from_secs(60), 490.0), Signal::Dark);
+
+        assert_eq!(
+            parking.update(Instant::from_secs(61), 1500.0),
+            Signal::Green
+        );
+    }
+}
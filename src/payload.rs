@@ -0,0 +1,146 @@
+use core::fmt::Error;
+
+use embassy_time::Instant;
+use heapless::String;
+use serde::Serialize;
+
+use crate::clock::Clock;
+use crate::distance::Unit;
+use crate::settings::DistanceUnit;
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+// a single reading, published as json so consumers know
+// which device sent it, when and in which unit
+#[derive(Serialize)]
+pub struct Reading<'a> {
+    // the mac address, same as in the topics
+    pub device: &'a str,
+    pub uptime_ms: u64,
+    // unix time, null when no ntp server is configured or reachable
+    pub timestamp_ms: Option<u64>,
+    pub distance: f64,
+    pub unit: &'static str,
+    // raw duration of the echo pulse
+    pub echo_us: u64,
+    pub status: &'static str,
+}
+
+impl<'a> Reading<'a> {
+    // `millimeters` is the filtered distance of the reading
+    pub fn new(
+        device: &'a str,
+        clock: &Clock,
+        now: Instant,
+        unit: &Unit,
+        millimeters: f64,
+        distance_unit: DistanceUnit,
+    ) -> Self {
+        Self {
+            device,
+            uptime_ms: now.as_millis(),
+            timestamp_ms: clock.unix_ms(now),
+            distance: distance_unit.from_millimeters(millimeters),
+            unit: distance_unit.as_str(),
+            echo_us: unit.echo.as_micros(),
+            status: unit.status.as_str(),
+        }
+    }
+
+    pub fn to_json<const N: usize>(&self) -> Result<String<N>> {
+        serde_json_core::to_string(self).map_err(|_| Error)
+    }
+}
+
+// any other value a mode measures, with the same device and time fields as a reading
+#[derive(Serialize)]
+pub struct Measurement<'a> {
+    pub device: &'a str,
+    pub uptime_ms: u64,
+    pub timestamp_ms: Option<u64>,
+    pub value: f64,
+    pub unit: &'static str,
+    // what the value means in modes that derive a state from it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<&'static str>,
+}
+
+impl<'a> Measurement<'a> {
+    pub fn new(
+        device: &'a str,
+        clock: &Clock,
+        now: Instant,
+        value: f64,
+        unit: &'static str,
+    ) -> Self {
+        Self {
+            device,
+            uptime_ms: now.as_millis(),
+            timestamp_ms: clock.unix_ms(now),
+            value,
+            unit,
+            state: None,
+        }
+    }
+
+    pub fn with_state(self, state: &'static str) -> Self {
+        Self {
+            state: Some(state),
+            ..self
+        }
+    }
+
+    pub fn to_json<const N: usize>(&self) -> Result<String<N>> {
+        serde_json_core::to_string(self).map_err(|_| Error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_time::Duration;
+
+    use super::*;
+    use crate::distance::Status;
+
+    fn unit() -> Unit {
+        Unit {
+            millimeters: 1234.0,
+            centimeters: 123.4,
+            decimeters: 12.34,
+            meters: 1.234,
+            echo: Duration::from_micros(7195),
+            status: Status::Valid,
+        }
+    }
+
+    #[test]
+    fn reading_without_a_synced_clock() {
+        let reading = Reading::new(
+            "28cdc1000001",
+            &Clock::new(),
+            Instant::from_millis(5000),
+            &unit(),
+            1200.0,
+            DistanceUnit::Centimeters,
+        );
+        assert_eq!(
+            reading.to_json::<256>().unwrap(),
+            r#"{"device":"28cdc1000001","uptime_ms":5000,"timestamp_ms":null,"distance":120.0,"unit":"cm","echo_us":7195,"status":"valid"}"#
+        );
+    }
+
+    #[test]
+    fn measurement_with_a_synced_clock_and_state() {
+        let now = Instant::from_millis(5000);
+        let clock = Clock::synced(1_700_000_000_000, now);
+        let measurement =
+            Measurement::new("28cdc1000001", &clock, now, 0.5, "m").with_state("amber");
+        assert_eq!(
+            measurement.to_json::<256>().unwrap(),
+            r#"{"device":"28cdc1000001","uptime_ms":5000,"timestamp_ms":1700000000000,"value":0.5,"unit":"m","state":"amber"}"#
+        );
+
+        let measurement = Measurement::new("28cdc1000001", &clock, now, 42.0, "passages");
+        assert!(!measurement.to_json::<256>().unwrap().contains("state"));
+    }
+}
@@ -0,0 +1,266 @@
+use core::str::FromStr;
+
+use embassy_time::{Duration, Instant};
+use embedded_hal::digital::OutputPin;
+
+// a running pump has to raise the level at least
+// this much every dry run period, otherwise it runs dry
+const DRY_RUN_RISE_MM: f64 = 10.0;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Command {
+    // switch on below the low mark and off above the high mark
+    Auto,
+    On,
+    Off,
+    // clear a dry run fault
+    Reset,
+}
+
+impl FromStr for Command {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "auto" => Ok(Command::Auto),
+            "on" => Ok(Command::On),
+            "off" => Ok(Command::Off),
+            "reset" => Ok(Command::Reset),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum State {
+    Off,
+    On,
+    // the pump was switched off because the level did not rise
+    DryRun,
+}
+
+impl State {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            State::Off => "off",
+            State::On => "on",
+            State::DryRun => "dry-run",
+        }
+    }
+}
+
+// a relay switching a pump that fills the tank
+pub struct Pump<R> {
+    relay: R,
+    // percentages
+    low: f64,
+    high: f64,
+    min_on: Duration,
+    min_off: Duration,
+    dry_run: Duration,
+    mode: Command,
+    state: State,
+    switched_at: Instant,
+    // level at the start of the current dry run period
+    check_started: Instant,
+    check_level: f64,
+    run_time: Duration,
+}
+
+impl<R: OutputPin> Pump<R> {
+    pub fn new(
+        mut relay: R,
+        low: f64,
+        high: f64,
+        min_on: Duration,
+        min_off: Duration,
+        dry_run: Duration,
+        now: Instant,
+    ) -> Self {
+        let _ = relay.set_low();
+        Self {
+            relay,
+            low,
+            high,
+            min_on,
+            min_off,
+            dry_run,
+            mode: Command::Auto,
+            state: State::Off,
+            switched_at: now,
+            check_started: now,
+            check_level: 0.0,
+            run_time: Duration::from_ticks(0),
+        }
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    // total time the pump has been running since boot
+    pub fn run_time(&self, now: Instant) -> Duration {
+        match self.state {
+            State::On => self.run_time + now.duration_since(self.switched_at),
+            _ => self.run_time,
+        }
+    }
+
+    pub fn command(&mut self, command: Command) {
+        match command {
+            Command::Reset => {
+                if self.state == State::DryRun {
+                    self.state = State::Off;
+                }
+            }
+            mode => self.mode = mode,
+        }
+    }
+
+    // returns true when the state has changed
+    pub fn update(&mut self, now: Instant, percentage: f64, millimeters: f64) -> bool {
+        let since_switch = now.duration_since(self.switched_at);
+        match self.state {
+            // latched until reset
+            State::DryRun => false,
+            State::On => {
+                if now.duration_since(self.check_started) >= self.dry_run {
+                    if millimeters - self.check_level < DRY_RUN_RISE_MM {
+                        return self.switch(now, State::DryRun, millimeters);
+                    }
+                    self.check_started = now;
+                    self.check_level = millimeters;
+                }
+
+                // the high mark keeps the tank from overflowing whatever the mode
+                let off = percentage >= self.high
+                    || (self.mode == Command::Off && since_switch >= self.min_on);
+                off && self.switch(now, State::Off, millimeters)
+            }
+            State::Off => {
+                let on = match self.mode {
+                    Command::On => percentage < self.high,
+                    Command::Off => false,
+                    _ => percentage <= self.low,
+                };
+                on && since_switch >= self.min_off && self.switch(now, State::On, millimeters)
+            }
+        }
+    }
+
+    // the level is unknown, a running pump is switched off until the next valid reading.
+    // returns true when the state has changed
+    pub fn stop(&mut self, now: Instant) -> bool {
+        let millimeters = self.check_level;
+        self.state == State::On && self.switch(now, State::Off, millimeters)
+    }
+
+    fn switch(&mut self, now: Instant, state: State, millimeters: f64) -> bool {
+        if self.state == State::On {
+            self.run_time += now.duration_since(self.switched_at);
+        }
+
+        let _ = self.relay.set_state((state == State::On).into());
+        self.state = state;
+        self.switched_at = now;
+        self.check_started = now;
+        self.check_level = millimeters;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::convert::Infallible;
+    use embedded_hal::digital::ErrorType;
+
+    struct Relay;
+
+    impl ErrorType for Relay {
+        type Error = Infallible;
+    }
+
+    impl OutputPin for Relay {
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            Ok(())
+        }
+    }
+
+    fn pump() -> Pump<Relay> {
+        Pump::new(
+            Relay,
+            20.0,
+            90.0,
+            Duration::from_secs(60),
+            Duration::from_secs(300),
+            Duration::from_secs(120),
+            Instant::from_secs(0),
+        )
+    }
+
+    #[test]
+    fn fills_between_the_marks() {
+        let mut pump = pump();
+        assert!(!pump.update(Instant::from_secs(100), 10.0, 100.0));
+        assert!(pump.update(Instant::from_secs(300), 10.0, 100.0));
+        assert_eq!(pump.state(), State::On);
+        assert!(!pump.update(Instant::from_secs(400), 50.0, 500.0));
+        assert!(pump.update(Instant::from_secs(410), 90.0, 900.0));
+        assert_eq!(pump.state(), State::Off);
+        assert_eq!(
+            pump.run_time(Instant::from_secs(500)),
+            Duration::from_secs(110)
+        );
+    }
+
+    #[test]
+    fn high_mark_stops_a_pump_switched_on_by_command() {
+        let mut pump = pump();
+        pump.command(Command::On);
+        assert!(!pump.update(Instant::from_secs(300), 95.0, 950.0));
+        assert!(pump.update(Instant::from_secs(300), 50.0, 500.0));
+        // right away, the minimum on time only holds back commands
+        assert!(pump.update(Instant::from_secs(301), 90.0, 900.0));
+        assert_eq!(pump.state(), State::Off);
+    }
+
+    #[test]
+    fn off_command_waits_for_the_minimum_on_time() {
+        let mut pump = pump();
+        assert!(pump.update(Instant::from_secs(300), 10.0, 100.0));
+        pump.command(Command::Off);
+        assert!(!pump.update(Instant::from_secs(330), 10.0, 105.0));
+        assert!(pump.update(Instant::from_secs(360), 10.0, 110.0));
+        assert_eq!(pump.state(), State::Off);
+    }
+
+    #[test]
+    fn dry_run_latches_until_reset() {
+        let mut pump = pump();
+        assert!(pump.update(Instant::from_secs(300), 10.0, 100.0));
+        assert!(pump.update(Instant::from_secs(420), 10.0, 105.0));
+        assert_eq!(pump.state(), State::DryRun);
+        assert!(!pump.update(Instant::from_secs(1000), 10.0, 105.0));
+
+        pump.command(Command::Reset);
+        assert_eq!(pump.state(), State::Off);
+        assert!(pump.update(Instant::from_secs(1000), 10.0, 105.0));
+    }
+
+    #[test]
+    fn unknown_level_stops_a_running_pump() {
+        let mut pump = pump();
+        assert!(!pump.stop(Instant::from_secs(300)));
+        assert!(pump.update(Instant::from_secs(300), 10.0, 100.0));
+        assert!(pump.stop(Instant::from_secs(310)));
+        assert_eq!(pump.state(), State::Off);
+        // and it waits for the minimum off time before filling again
+        assert!(!pump.update(Instant::from_secs(320), 10.0, 100.0));
+        assert!(pump.update(Instant::from_secs(610), 10.0, 100.0));
+    }
+}
@@ -0,0 +1,74 @@
+use core::fmt::Error;
+use core::str::FromStr;
+
+use embassy_net::dns::DnsQueryType;
+use embassy_net::driver::Driver;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{IpAddress, Ipv4Address, Stack};
+use embassy_time::{with_timeout, Duration, Instant};
+
+use crate::mdns::{self, MAX_PACKET_SIZE};
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+const MDNS_ADDRESS: Ipv4Address = Ipv4Address::new(224, 0, 0, 251);
+const MDNS_PORT: u16 = 5353;
+const MDNS_TIMEOUT: Duration = Duration::from_secs(3);
+
+// Resolves an ip address, a hostname through the dns servers handed out
+// by dhcp, or a `.local` name through multicast dns.
+pub async fn resolve<D: Driver>(stack: &Stack<D>, host: &str) -> Result<Ipv4Address> {
+    if let Ok(address) = Ipv4Address::from_str(host) {
+        return Ok(address);
+    }
+    if host.trim_end_matches('.').ends_with(".local") {
+        return query_mdns(stack, host).await;
+    }
+
+    let addresses = stack
+        .dns_query(host, DnsQueryType::A)
+        .await
+        .map_err(|_| Error)?;
+    match addresses.first() {
+        Some(IpAddress::Ipv4(address)) => Ok(*address),
+        _ => Err(Error),
+    }
+}
+
+// A one-shot query from an ephemeral port, responders answer
+// with unicast so there is no need to join the multicast group.
+async fn query_mdns<D: Driver>(stack: &Stack<D>, host: &str) -> Result<Ipv4Address> {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut rx_buffer = [0u8; MAX_PACKET_SIZE];
+    let mut tx_buffer = [0u8; MAX_PACKET_SIZE];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    socket.bind(0).map_err(|_| Error)?;
+
+    // the id tells our answers apart from those to other queries
+    let id = Instant::now().as_ticks() as u16;
+    let query = mdns::query(host, id)?;
+    socket
+        .send_to(&query, (MDNS_ADDRESS, MDNS_PORT))
+        .await
+        .map_err(|_| Error)?;
+
+    let started = Instant::now();
+    let mut packet = [0u8; MAX_PACKET_SIZE];
+    while started.elapsed() < MDNS_TIMEOUT {
+        let remaining = MDNS_TIMEOUT - started.elapsed();
+        let Ok(Ok((len, _))) = with_timeout(remaining, socket.recv_from(&mut packet)).await else {
+            break;
+        };
+        if let Some(address) = mdns::parse_answer(&packet[..len], id) {
+            return Ok(Ipv4Address::from_bytes(&address));
+        }
+    }
+    Err(Error)
+}
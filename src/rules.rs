@@ -0,0 +1,242 @@
+use core::fmt::Error;
+use core::str::FromStr;
+
+use embassy_time::{Duration, Instant};
+use heapless::{String, Vec};
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+// Rules are written as `<condition>:<seconds>:<actions>` and separated by commas.
+//
+//   <300:5:alert+gpio15=1,>2000:10:gpio15=0
+//
+// reads as "if the distance is below 300mm for 5 seconds, publish an alert and
+// set GP15 high. if the distance is above 2000mm for 10 seconds set GP15 low".
+
+pub const MAX_RULES: usize = 4;
+pub const MAX_LEN: usize = 128;
+const MAX_ACTIONS: usize = 2;
+
+// the pins rules are allowed to drive
+pub const OUTPUT_PINS: [u8; 2] = [14, 15];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Comparison {
+    Below,
+    Above,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Action {
+    // publish the rule number on `<prefix>/<id>/rule`
+    Alert,
+    // index into `OUTPUT_PINS`
+    Gpio { output: usize, high: bool },
+}
+
+impl FromStr for Action {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s == "alert" {
+            return Ok(Action::Alert);
+        }
+
+        let (pin, level) = s
+            .strip_prefix("gpio")
+            .and_then(|gpio| gpio.split_once('='))
+            .ok_or(Error)?;
+        let pin = u8::from_str(pin).map_err(|_| Error)?;
+        let output = OUTPUT_PINS.iter().position(|&p| p == pin).ok_or(Error)?;
+        let high = match level {
+            "1" => true,
+            "0" => false,
+            _ => return Err(Error),
+        };
+
+        Ok(Action::Gpio { output, high })
+    }
+}
+
+pub struct Rule {
+    comparison: Comparison,
+    distance: f64,
+    hold: Duration,
+    actions: Vec<Action, MAX_ACTIONS>,
+    // when the condition started to hold
+    since: Option<Instant>,
+    // rules fire once every time their condition holds
+    fired: bool,
+}
+
+impl FromStr for Rule {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.trim().splitn(3, ':');
+        let condition = parts.next().ok_or(Error)?;
+        let hold = parts.next().ok_or(Error)?;
+        let actions = parts.next().ok_or(Error)?;
+
+        let (comparison, distance) = if let Some(distance) = condition.strip_prefix('<') {
+            (Comparison::Below, distance)
+        } else if let Some(distance) = condition.strip_prefix('>') {
+            (Comparison::Above, distance)
+        } else {
+            return Err(Error);
+        };
+
+        let mut rule = Rule {
+            comparison,
+            distance: f64::from_str(distance).map_err(|_| Error)?,
+            hold: Duration::from_secs(u64::from_str(hold).map_err(|_| Error)?),
+            actions: Vec::new(),
+            since: None,
+            fired: false,
+        };
+        for action in actions.split('+') {
+            rule.actions
+                .push(Action::from_str(action)?)
+                .map_err(|_| Error)?;
+        }
+
+        Ok(rule)
+    }
+}
+
+impl Rule {
+    fn update(&mut self, now: Instant, distance: f64) -> bool {
+        let holds = match self.comparison {
+            Comparison::Below => distance < self.distance,
+            Comparison::Above => distance > self.distance,
+        };
+        if !holds {
+            self.since = None;
+            self.fired = false;
+            return false;
+        }
+
+        let since = *self.since.get_or_insert(now);
+        if self.fired || now.duration_since(since) < self.hold {
+            return false;
+        }
+
+        self.fired = true;
+        true
+    }
+}
+
+pub struct Rules {
+    source: String<MAX_LEN>,
+    rules: Vec<Rule, MAX_RULES>,
+}
+
+impl FromStr for Rules {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let mut rules = Rules {
+            source: String::from_str(s).map_err(|_| Error)?,
+            rules: Vec::new(),
+        };
+        for rule in s.split(',').filter(|r| !r.trim().is_empty()) {
+            rules.rules.push(Rule::from_str(rule)?).map_err(|_| Error)?;
+        }
+
+        Ok(rules)
+    }
+}
+
+impl Rules {
+    pub fn as_str(&self) -> &str {
+        self.source.as_str()
+    }
+
+    // returns the numbers of the rules that fired
+    pub fn update(&mut self, now: Instant, distance: f64) -> Vec<usize, MAX_RULES> {
+        let mut fired = Vec::new();
+        for (number, rule) in self.rules.iter_mut().enumerate() {
+            if rule.update(now, distance) {
+                // cannot fail, there are at most MAX_RULES rules
+                let _ = fired.push(number);
+            }
+        }
+        fired
+    }
+
+    pub fn actions(&self, number: usize) -> &[Action] {
+        &self.rules[number].actions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_conditions_and_actions() {
+        let rules = Rules::from_str(" <300:5:alert+gpio15=1,>2000:10:gpio14=0 ").unwrap();
+        assert_eq!(rules.as_str(), "<300:5:alert+gpio15=1,>2000:10:gpio14=0");
+        assert_eq!(
+            rules.actions(0),
+            [
+                Action::Alert,
+                Action::Gpio {
+                    output: 1,
+                    high: true
+                }
+            ]
+        );
+        assert_eq!(
+            rules.actions(1),
+            [Action::Gpio {
+                output: 0,
+                high: false
+            }]
+        );
+        assert!(Rules::from_str("").is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_rules() {
+        for rules in [
+            "=300:5:alert",
+            "<300:alert",
+            "<abc:5:alert",
+            "<300:-5:alert",
+            "<300:5:beep",
+            "<300:5:gpio2=1",
+            "<300:5:gpio15=2",
+            "<300:5:alert+gpio15=1+gpio14=1",
+            "<1:1:alert,<2:1:alert,<3:1:alert,<4:1:alert,<5:1:alert",
+        ] {
+            assert!(Rules::from_str(rules).is_err(), "{}", rules);
+        }
+    }
+
+    #[test]
+    fn fires_once_after_the_hold_time() {
+        let mut rules = Rules::from_str("<300:5:alert").unwrap();
+        assert!(rules.update(Instant::from_secs(0), 200.0).is_empty());
+        assert!(rules.update(Instant::from_secs(4), 200.0).is_empty());
+        assert_eq!(rules.update(Instant::from_secs(5), 200.0), [0]);
+        assert!(rules.update(Instant::from_secs(6), 200.0).is_empty());
+
+        // fires again once the condition has been broken
+        assert!(rules.update(Instant::from_secs(7), 400.0).is_empty());
+        assert!(rules.update(Instant::from_secs(8), 200.0).is_empty());
+        assert_eq!(rules.update(Instant::from_secs(13), 200.0), [0]);
+    }
+
+    #[test]
+    fn a_broken_condition_restarts_the_hold_time() {
+        let mut rules = Rules::from_str("<300:5:alert,>2000:0:gpio15=0").unwrap();
+        rules.update(Instant::from_secs(0), 200.0);
+        rules.update(Instant::from_secs(3), 300.0);
+        assert!(rules.update(Instant::from_secs(5), 200.0).is_empty());
+        assert_eq!(rules.update(Instant::from_secs(10), 200.0), [0]);
+        // no hold time fires right away
+        assert_eq!(rules.update(Instant::from_secs(11), 2500.0), [1]);
+    }
+}
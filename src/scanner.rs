@@ -0,0 +1,92 @@
+use embassy_rp::peripherals::{PIN_18, PWM_CH1};
+use embassy_rp::pwm::{Config, Pwm};
+use embassy_time::Timer;
+use fixed::types::U12F4;
+
+use crate::distance::Mount;
+use crate::hc_sr04::HCSR04;
+use crate::sweep::{self, Angles, Reference, Sweep, MAX_POINTS};
+
+// 125MHz system clock divided down to 1.953125MHz,
+// a 20ms (50Hz) servo period then fits in the 16 bit counter
+const DIVIDER: u32 = 64;
+const TICKS_PER_MICROSECOND: f64 = 125.0 / DIVIDER as f64;
+const PERIOD_US: f64 = 20_000.0;
+// pulse widths for 0 and 180 degrees of a typical hobby servo
+const MIN_PULSE_US: f64 = 500.0;
+const MAX_PULSE_US: f64 = 2500.0;
+// time for the servo to reach the next angle
+const SETTLE_MS: u64 = 100;
+
+// a hobby servo on GP18
+pub struct Servo {
+    pwm: Pwm<'static, PWM_CH1>,
+    config: Config,
+}
+
+impl Servo {
+    pub fn new(slice: PWM_CH1, pin: PIN_18) -> Self {
+        let mut config = Config::default();
+        config.divider = U12F4::from_num(DIVIDER);
+        config.top = (PERIOD_US * TICKS_PER_MICROSECOND) as u16 - 1;
+        config.compare_a = 0;
+        let pwm = Pwm::new_output_a(slice, pin, config.clone());
+
+        Self { pwm, config }
+    }
+
+    pub fn set_angle(&mut self, degrees: u8) {
+        let degrees = degrees.min(180) as f64;
+        let pulse = MIN_PULSE_US + (MAX_PULSE_US - MIN_PULSE_US) * degrees / 180.0;
+        self.config.compare_a = (pulse * TICKS_PER_MICROSECOND) as u16;
+        self.pwm.set_config(&self.config);
+    }
+}
+
+pub struct Scanner {
+    servo: Servo,
+    angles: Angles,
+    reference: Reference,
+}
+
+impl Scanner {
+    pub fn new(servo: Servo, start: u8, end: u8, step: u8, threshold: u16) -> sweep::Result<Self> {
+        Ok(Self {
+            servo,
+            angles: Angles::new(start, end, step)?,
+            reference: Reference::new(threshold),
+        })
+    }
+
+    pub fn angle(&self, index: usize) -> u8 {
+        self.angles.get(index)
+    }
+
+    pub async fn sweep(&mut self, sensor: &mut HCSR04, mount: Mount) -> Sweep {
+        let mut sweep = Sweep::new();
+        for index in 0..self.angles.len() {
+            self.servo.set_angle(self.angles.get(index));
+            Timer::after_millis(SETTLE_MS).await;
+            let millimeters = match sensor.measure().await {
+                Ok(echo) => mount.correct(echo).millimeters as u16,
+                Err(_) => 0,
+            };
+            // cannot fail, there are at most MAX_POINTS angles
+            let _ = sweep.push(millimeters);
+        }
+        // head back to the start while the sweep is published
+        self.servo.set_angle(self.angles.start());
+
+        sweep
+    }
+
+    // forgets the reference, the next sweep becomes the new one
+    pub fn reset_reference(&mut self) {
+        self.reference.reset();
+    }
+
+    // returns the indexes of the angles that changed compared to the reference
+    pub fn compare(&mut self, sweep: &Sweep) -> heapless::Vec<usize, MAX_POINTS> {
+        self.reference.compare(sweep)
+    }
+}
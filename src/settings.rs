@@ -0,0 +1,211 @@
+use core::fmt::Error;
+use core::str::FromStr;
+
+use heapless::String;
+use serde::{Deserialize, Serialize};
+
+use crate::distance::Mount;
+use crate::filter;
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+// longest settings document, as stored in flash
+pub const MAX_LEN: usize = 256;
+// leaves room for the device id and topic names, see src/topic.rs
+const MAX_PREFIX_LEN: usize = 24;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum DistanceUnit {
+    #[serde(rename = "mm")]
+    Millimeters,
+    #[serde(rename = "cm")]
+    Centimeters,
+    #[serde(rename = "m")]
+    Meters,
+    #[serde(rename = "in")]
+    Inches,
+}
+
+impl DistanceUnit {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DistanceUnit::Millimeters => "mm",
+            DistanceUnit::Centimeters => "cm",
+            DistanceUnit::Meters => "m",
+            DistanceUnit::Inches => "in",
+        }
+    }
+
+    pub fn from_millimeters(&self, millimeters: f64) -> f64 {
+        match self {
+            DistanceUnit::Millimeters => millimeters,
+            DistanceUnit::Centimeters => millimeters / 10.0,
+            DistanceUnit::Meters => millimeters / 1000.0,
+            DistanceUnit::Inches => millimeters / 25.4,
+        }
+    }
+}
+
+// Settings that can be changed at runtime over mqtt. They start out with the
+// compile time values and are stored in flash once they have been changed.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct Settings {
+    pub topic_prefix: String<MAX_PREFIX_LEN>,
+    // of the distance published in readings
+    pub unit: DistanceUnit,
+    // readings this much closer than the baseline are an intrusion
+    pub threshold_mm: f64,
+    // pause after an intrusion has been published, stored
+    // settings from before the rename still use the old name
+    #[serde(alias = "interval_ms")]
+    pub intrusion_pause_ms: u64,
+    pub tank_interval_secs: u64,
+    // readings are smoothed with the median of this many, 1 turns it off
+    pub median_window: usize,
+    pub mount_offset_mm: f64,
+    pub mount_tilt_degrees: f64,
+}
+
+// a partial document, only the fields that are present change
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Update<'a> {
+    topic_prefix: Option<&'a str>,
+    unit: Option<DistanceUnit>,
+    threshold_mm: Option<f64>,
+    intrusion_pause_ms: Option<u64>,
+    tank_interval_secs: Option<u64>,
+    median_window: Option<usize>,
+    mount_offset_mm: Option<f64>,
+    mount_tilt_degrees: Option<f64>,
+}
+
+impl Settings {
+    pub fn from_json(json: &[u8]) -> Result<Self> {
+        let (settings, _): (Settings, usize) =
+            serde_json_core::from_slice(json).map_err(|_| Error)?;
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    pub fn to_json<const N: usize>(&self) -> Result<String<N>> {
+        serde_json_core::to_string(self).map_err(|_| Error)
+    }
+
+    // returns the settings with the update applied, or an error
+    // without changing anything when any of the values is invalid
+    pub fn update(&self, json: &[u8]) -> Result<Self> {
+        let (update, _): (Update, usize) = serde_json_core::from_slice(json).map_err(|_| Error)?;
+
+        let mut settings = self.clone();
+        if let Some(prefix) = update.topic_prefix {
+            settings.topic_prefix = String::from_str(prefix).map_err(|_| Error)?;
+        }
+        settings.unit = update.unit.unwrap_or(settings.unit);
+        settings.threshold_mm = update.threshold_mm.unwrap_or(settings.threshold_mm);
+        settings.intrusion_pause_ms = update
+            .intrusion_pause_ms
+            .unwrap_or(settings.intrusion_pause_ms);
+        settings.tank_interval_secs = update
+            .tank_interval_secs
+            .unwrap_or(settings.tank_interval_secs);
+        settings.median_window = update.median_window.unwrap_or(settings.median_window);
+        settings.mount_offset_mm = update.mount_offset_mm.unwrap_or(settings.mount_offset_mm);
+        settings.mount_tilt_degrees = update
+            .mount_tilt_degrees
+            .unwrap_or(settings.mount_tilt_degrees);
+
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    pub fn mount(&self) -> Mount {
+        Mount {
+            offset_mm: self.mount_offset_mm,
+            tilt_degrees: self.mount_tilt_degrees,
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        // wildcards and empty levels would break the topics
+        let prefix = self.topic_prefix.as_str();
+        let valid_prefix = !prefix.is_empty()
+            && !prefix.starts_with('/')
+            && !prefix.ends_with('/')
+            && !prefix.contains(['+', '#']);
+        // NaN fails these as well
+        let valid_threshold = self.threshold_mm > 0.0;
+        let valid_tilt = libm::fabs(self.mount_tilt_degrees) < 90.0;
+
+        if !valid_prefix
+            || !valid_threshold
+            || !(10..=60_000).contains(&self.intrusion_pause_ms)
+            || !(1..=3600).contains(&self.tank_interval_secs)
+            || !(1..=filter::MAX_WINDOW).contains(&self.median_window)
+            || !valid_tilt
+            || !self.mount_offset_mm.is_finite()
+        {
+            return Err(Error);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> Settings {
+        Settings {
+            topic_prefix: String::from_str("pico").unwrap(),
+            unit: DistanceUnit::Millimeters,
+            threshold_mm: 200.0,
+            intrusion_pause_ms: 100,
+            tank_interval_secs: 10,
+            median_window: 1,
+            mount_offset_mm: 0.0,
+            mount_tilt_degrees: 0.0,
+        }
+    }
+
+    #[test]
+    fn update_changes_only_the_given_fields() {
+        let updated = settings()
+            .update(br#"{"unit":"cm","median_window":5}"#)
+            .unwrap();
+        assert_eq!(updated.unit, DistanceUnit::Centimeters);
+        assert_eq!(updated.median_window, 5);
+        assert_eq!(updated.topic_prefix.as_str(), "pico");
+        assert_eq!(updated.threshold_mm, 200.0);
+    }
+
+    #[test]
+    fn update_rejects_the_whole_document() {
+        let settings = settings();
+        for json in [
+            r#"{"unit":"cm","threshold_mm":-1}"#,
+            r#"{"topic_prefix":"pico/#"}"#,
+            r#"{"topic_prefix":"/pico"}"#,
+            r#"{"mount_tilt_degrees":90}"#,
+            r#"{"median_window":0}"#,
+            r#"{"colour":"red"}"#,
+            r#"{"unit":"feet"}"#,
+            "not json",
+        ] {
+            assert!(settings.update(json.as_bytes()).is_err(), "{}", json);
+        }
+    }
+
+    #[test]
+    fn reads_the_old_name_of_the_intrusion_pause() {
+        let json = br#"{"topic_prefix":"pico","unit":"mm","threshold_mm":200.0,"interval_ms":250,"tank_interval_secs":10,"median_window":1,"mount_offset_mm":0.0,"mount_tilt_degrees":0.0}"#;
+        assert_eq!(Settings::from_json(json).unwrap().intrusion_pause_ms, 250);
+    }
+
+    #[test]
+    fn survives_a_round_trip_through_flash() {
+        let settings = settings().update(br#"{"unit":"in"}"#).unwrap();
+        let json = settings.to_json::<MAX_LEN>().unwrap();
+        assert!(Settings::from_json(json.as_bytes()).unwrap() == settings);
+    }
+}
@@ -0,0 +1,175 @@
+use core::str::FromStr;
+
+use embassy_time::{Duration, Instant};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Pattern {
+    // the high tone without a break
+    Continuous,
+    // the high tone switched on and off
+    Beep,
+    // alternating between the low and high tone
+    Siren,
+}
+
+impl Pattern {
+    // the tone and how long to hold it for every step of the pattern, 0 is silent
+    pub fn step(&self, index: usize, low_hz: u32, high_hz: u32) -> (u32, Duration) {
+        let odd = index % 2 == 1;
+        match self {
+            Pattern::Continuous => (high_hz, Duration::from_millis(500)),
+            Pattern::Beep if odd => (0, Duration::from_millis(200)),
+            Pattern::Beep => (high_hz, Duration::from_millis(200)),
+            Pattern::Siren if odd => (high_hz, Duration::from_millis(500)),
+            Pattern::Siren => (low_hz, Duration::from_millis(500)),
+        }
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "continuous" => Ok(Pattern::Continuous),
+            "beep" => Ok(Pattern::Beep),
+            "siren" => Ok(Pattern::Siren),
+            _ => Err(()),
+        }
+    }
+}
+
+// what makes the siren sound
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Trigger {
+    // every intrusion, whatever the state of the alarm panel
+    Intrusion,
+    // only once the alarm panel is triggered
+    Panel,
+}
+
+impl FromStr for Trigger {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "intrusion" => Ok(Trigger::Intrusion),
+            "panel" => Ok(Trigger::Panel),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Command {
+    Sound,
+    Silence,
+}
+
+impl FromStr for Command {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "sound" => Ok(Command::Sound),
+            "silence" => Ok(Command::Silence),
+            _ => Err(()),
+        }
+    }
+}
+
+// Whether the siren sounds. It sounds until silenced, or on its own
+// for at most `max_duration` so a forgotten alarm doesn't go on forever.
+pub struct Sounding {
+    max_duration: Duration,
+    since: Option<Instant>,
+}
+
+impl Sounding {
+    pub fn new(max_duration: Duration) -> Self {
+        Self {
+            max_duration,
+            since: None,
+        }
+    }
+
+    pub fn command(&mut self, now: Instant, command: Command) {
+        match command {
+            // sounding again doesn't extend the maximum duration
+            Command::Sound if self.is_sounding(now) => (),
+            Command::Sound => self.since = Some(now),
+            Command::Silence => self.since = None,
+        }
+    }
+
+    pub fn is_sounding(&self, now: Instant) -> bool {
+        self.since
+            .is_some_and(|since| now.duration_since(since) < self.max_duration)
+    }
+}
+
+// the top of a pwm counter running at `counter_hz` for a square wave
+// at `frequency`, none when the frequency is 0 and the tone is silent
+pub fn pwm_top(counter_hz: u32, frequency: u32) -> Option<u16> {
+    let period = counter_hz.checked_div(frequency)?;
+    Some((period.clamp(2, u16::MAX as u32) - 1) as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stops_on_its_own_after_the_maximum_duration() {
+        let mut sounding = Sounding::new(Duration::from_secs(60));
+        assert!(!sounding.is_sounding(Instant::from_secs(0)));
+        sounding.command(Instant::from_secs(10), Command::Sound);
+        assert!(sounding.is_sounding(Instant::from_secs(69)));
+        // another intrusion while sounding
+        sounding.command(Instant::from_secs(50), Command::Sound);
+        assert!(!sounding.is_sounding(Instant::from_secs(70)));
+
+        sounding.command(Instant::from_secs(80), Command::Sound);
+        assert!(sounding.is_sounding(Instant::from_secs(80)));
+    }
+
+    #[test]
+    fn silence_stops_it_right_away() {
+        let mut sounding = Sounding::new(Duration::from_secs(60));
+        sounding.command(Instant::from_secs(0), Command::Sound);
+        sounding.command(Instant::from_secs(5), Command::Silence);
+        assert!(!sounding.is_sounding(Instant::from_secs(5)));
+        // silencing a quiet siren does nothing
+        sounding.command(Instant::from_secs(6), Command::Silence);
+        assert!(!sounding.is_sounding(Instant::from_secs(6)));
+    }
+
+    #[test]
+    fn patterns_alternate_their_tones() {
+        let beep: [(u32, u64); 3] = core::array::from_fn(|index| {
+            let (frequency, duration) = Pattern::Beep.step(index, 1000, 2000);
+            (frequency, duration.as_millis())
+        });
+        assert_eq!(beep, [(2000, 200), (0, 200), (2000, 200)]);
+        assert_eq!(Pattern::Siren.step(0, 1000, 2000).0, 1000);
+        assert_eq!(Pattern::Siren.step(1, 1000, 2000).0, 2000);
+        assert_eq!(Pattern::Continuous.step(1, 1000, 2000).0, 2000);
+    }
+
+    #[test]
+    fn parses_commands_and_triggers() {
+        assert_eq!(Command::from_str(" silence\n"), Ok(Command::Silence));
+        assert!(Command::from_str("stop").is_err());
+        assert_eq!(Trigger::from_str("panel"), Ok(Trigger::Panel));
+        assert_eq!(Pattern::from_str("beep"), Ok(Pattern::Beep));
+    }
+
+    #[test]
+    fn silent_and_extreme_tones() {
+        assert_eq!(pwm_top(7_812_500, 0), None);
+        assert_eq!(pwm_top(7_812_500, 2500), Some(3124));
+        // too low for the counter, and too high to be a square wave
+        assert_eq!(pwm_top(7_812_500, 50), Some(u16::MAX - 1));
+        assert_eq!(pwm_top(7_812_500, 10_000_000), Some(1));
+    }
+}
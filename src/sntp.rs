@@ -0,0 +1,72 @@
+use core::cell::Cell;
+use core::fmt::Error;
+
+use embassy_net::driver::Driver;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{Ipv4Address, Stack};
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+use log::{debug, warn};
+
+use crate::clock::{self, Clock, NTP_PACKET_SIZE};
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+const NTP_PORT: u16 = 123;
+const TIMEOUT: Duration = Duration::from_secs(5);
+// a server that didn't answer is asked again after this long
+const RETRY: Duration = Duration::from_secs(60);
+
+// read by the measurements and the tls handshake, unsynced until the server has answered
+static CLOCK: Mutex<CriticalSectionRawMutex, Cell<Clock>> = Mutex::new(Cell::new(Clock::new()));
+
+pub fn clock() -> Clock {
+    CLOCK.lock(|clock| clock.get())
+}
+
+// keeps asking until the server answers, the time of day must not
+// stay unknown because the server was briefly unreachable at boot
+pub async fn keep_syncing<D: Driver>(stack: &Stack<D>, server: Ipv4Address) {
+    loop {
+        match sync(stack, server).await {
+            Ok(synced) => {
+                debug!("clock synced over sntp");
+                CLOCK.lock(|clock| clock.set(synced));
+                return;
+            }
+            Err(e) => warn!("failed to sync clock, retrying: {:?}", e),
+        }
+        Timer::after(RETRY).await;
+    }
+}
+
+// queries the wall clock time once
+async fn sync<D: Driver>(stack: &Stack<D>, server: Ipv4Address) -> Result<Clock> {
+    let mut rx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut rx_buffer = [0u8; NTP_PACKET_SIZE];
+    let mut tx_buffer = [0u8; NTP_PACKET_SIZE];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    socket.bind(0).map_err(|_| Error)?;
+
+    // version 4, client mode, everything else can be left empty
+    let mut packet = [0u8; NTP_PACKET_SIZE];
+    packet[0] = 0x23;
+    socket
+        .send_to(&packet, (server, NTP_PORT))
+        .await
+        .map_err(|_| Error)?;
+    let (len, _) = with_timeout(TIMEOUT, socket.recv_from(&mut packet))
+        .await
+        .map_err(|_| Error)?
+        .map_err(|_| Error)?;
+
+    let unix_ms = clock::ntp_unix_ms(&packet[..len]).ok_or(Error)?;
+    Ok(Clock::synced(unix_ms, Instant::now()))
+}
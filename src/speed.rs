@@ -0,0 +1,109 @@
+use embassy_time::{Duration, Instant};
+use heapless::Deque;
+
+use crate::stats;
+
+pub const MAX_SAMPLES: usize = 8;
+
+// only the last second of readings is used, anything
+// older says little about how fast something moves now
+const WINDOW: Duration = Duration::from_secs(1);
+
+// approaching slower than this is standing still
+const MIN_APPROACH_SPEED: f64 = 0.2;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Approach {
+    Person,
+    Vehicle,
+}
+
+impl Approach {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Approach::Person => "person",
+            Approach::Vehicle => "vehicle",
+        }
+    }
+}
+
+pub struct SpeedEstimator {
+    // approaching faster than this in m/s is a vehicle
+    vehicle_speed: f64,
+    samples: Deque<(Instant, f64), MAX_SAMPLES>,
+}
+
+impl SpeedEstimator {
+    pub fn new(vehicle_speed: f64) -> Self {
+        Self {
+            vehicle_speed,
+            samples: Deque::new(),
+        }
+    }
+
+    // returns the speed in m/s, negative while approaching
+    // the sensor and positive while receding
+    pub fn update(&mut self, now: Instant, millimeters: f64) -> Option<f64> {
+        while let Some(&(instant, _)) = self.samples.front() {
+            if now.duration_since(instant) <= WINDOW && !self.samples.is_full() {
+                break;
+            }
+            self.samples.pop_front();
+        }
+        // cannot fail, there is always room after the loop above
+        let _ = self.samples.push_back((now, millimeters));
+
+        if self.samples.len() < 2 {
+            return None;
+        }
+
+        Some(stats::slope(&self.samples) / 1000.0)
+    }
+
+    pub fn classify(&self, speed: f64) -> Option<Approach> {
+        let approaching = -speed;
+        if approaching > self.vehicle_speed {
+            Some(Approach::Vehicle)
+        } else if approaching > MIN_APPROACH_SPEED {
+            Some(Approach::Person)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn speed_from_two_readings() {
+        let mut speeds = SpeedEstimator::new(3.0);
+        assert_eq!(speeds.update(Instant::from_millis(0), 3000.0), None);
+        let speed = speeds.update(Instant::from_millis(500), 2500.0).unwrap();
+        assert!((speed + 1.0).abs() < 1e-9, "{}", speed);
+        let speed = speeds.update(Instant::from_millis(1000), 2500.0).unwrap();
+        assert!(speed < 0.0, "{}", speed);
+    }
+
+    #[test]
+    fn old_readings_are_forgotten() {
+        let mut speeds = SpeedEstimator::new(3.0);
+        speeds.update(Instant::from_millis(0), 3000.0);
+        // only the new one is within the window
+        assert_eq!(speeds.update(Instant::from_millis(1500), 1000.0), None);
+        let speed = speeds.update(Instant::from_millis(2000), 1500.0).unwrap();
+        assert!((speed - 1.0).abs() < 1e-9, "{}", speed);
+    }
+
+    #[test]
+    fn classifies_by_approach_speed() {
+        let speeds = SpeedEstimator::new(3.0);
+        assert_eq!(speeds.classify(-3.1), Some(Approach::Vehicle));
+        assert_eq!(speeds.classify(-3.0), Some(Approach::Person));
+        assert_eq!(speeds.classify(-0.21), Some(Approach::Person));
+        assert_eq!(speeds.classify(-0.2), None);
+        // receding
+        assert_eq!(speeds.classify(5.0), None);
+    }
+}
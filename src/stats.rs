@@ -0,0 +1,27 @@
+use embassy_time::Instant;
+use heapless::Deque;
+
+// least squares fit of the samples, in units per second.
+// a single noisy reading barely moves the result.
+pub fn slope<const N: usize>(samples: &Deque<(Instant, f64), N>) -> f64 {
+    let Some(&(oldest, _)) = samples.front() else {
+        return 0.0;
+    };
+
+    let (mut count, mut sum_x, mut sum_y, mut sum_xy, mut sum_xx) = (0.0, 0.0, 0.0, 0.0, 0.0);
+    for &(instant, value) in samples.iter() {
+        let x = instant.duration_since(oldest).as_millis() as f64 / 1000.0;
+        count += 1.0;
+        sum_x += x;
+        sum_y += value;
+        sum_xy += x * value;
+        sum_xx += x * x;
+    }
+
+    let denominator = count * sum_xx - sum_x * sum_x;
+    if denominator == 0.0 {
+        return 0.0;
+    }
+
+    (count * sum_xy - sum_x * sum_y) / denominator
+}
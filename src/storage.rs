@@ -0,0 +1,143 @@
+use core::fmt::Error;
+
+use embassy_rp::flash::{Blocking, Flash, ERASE_SIZE};
+use embassy_rp::peripherals::FLASH;
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+pub const FLASH_SIZE: usize = 2 * 1024 * 1024;
+
+// The last sectors of the flash are reserved for storage, one sector per slot.
+// memory.x keeps the firmware out of this region, keep the two in sync.
+const SLOT_COUNT: usize = 8;
+const STORAGE_OFFSET: u32 = (FLASH_SIZE - SLOT_COUNT * ERASE_SIZE) as u32;
+
+// 2 byte length + 1 byte checksum
+const RECORD_OVERHEAD: usize = 3;
+// erased flash reads as 0xFF
+const EMPTY_LENGTH: u16 = 0xFFFF;
+
+// Sectors before the end of the flash, counting from the end keeps the
+// slots where they are when the region grows, new slots go further down.
+#[derive(Clone, Copy)]
+pub enum Slot {
+    Door = 1,
+    Rules = 2,
+    Alarm = 3,
+    Counter = 4,
+    Settings = 5,
+    Anomaly = 6,
+}
+
+// Each slot is an append-only log of records. Only the latest valid record
+// is returned and the sector is only erased once it is full, so values that
+// change often don't wear out the flash.
+pub struct Storage {
+    flash: Flash<'static, FLASH, Blocking, FLASH_SIZE>,
+}
+
+impl Storage {
+    pub fn new(flash: FLASH) -> Self {
+        Self {
+            flash: Flash::new_blocking(flash),
+        }
+    }
+
+    // reads the latest record of the slot into `buf` and returns its length
+    pub fn read(&mut self, slot: Slot, buf: &mut [u8]) -> Result<usize> {
+        let (_, latest) = self.scan(slot, buf)?;
+        let (offset, len) = latest.ok_or(Error)?;
+        self.flash
+            .blocking_read(offset, &mut buf[..len])
+            .map_err(|_| Error)?;
+
+        Ok(len)
+    }
+
+    pub fn write(&mut self, slot: Slot, data: &[u8]) -> Result<()> {
+        if data.len() + RECORD_OVERHEAD > ERASE_SIZE {
+            return Err(Error);
+        }
+
+        let start = slot_offset(slot);
+        let (mut offset, _) = self.scan(slot, &mut [])?;
+        if offset + (data.len() + RECORD_OVERHEAD) as u32 > start + ERASE_SIZE as u32 {
+            self.flash
+                .blocking_erase(start, start + ERASE_SIZE as u32)
+                .map_err(|_| Error)?;
+            offset = start;
+        }
+
+        let len = (data.len() as u16).to_le_bytes();
+        self.flash.blocking_write(offset, &len).map_err(|_| Error)?;
+        self.flash
+            .blocking_write(offset + 2, data)
+            .map_err(|_| Error)?;
+        self.flash
+            .blocking_write(offset + 2 + data.len() as u32, &[checksum(data)])
+            .map_err(|_| Error)?;
+
+        Ok(())
+    }
+
+    // erases every slot
+    pub fn clear(&mut self) -> Result<()> {
+        self.flash
+            .blocking_erase(STORAGE_OFFSET, FLASH_SIZE as u32)
+            .map_err(|_| Error)
+    }
+
+    // walks the records of a slot and returns the offset where the next record
+    // can be written and the position of the latest valid record. `buf` is used
+    // as scratch space to verify records, records that don't fit are skipped.
+    fn scan(&mut self, slot: Slot, buf: &mut [u8]) -> Result<(u32, Option<(u32, usize)>)> {
+        let start = slot_offset(slot);
+        let end = start + ERASE_SIZE as u32;
+        let mut offset = start;
+        let mut latest = None;
+
+        while offset + RECORD_OVERHEAD as u32 <= end {
+            let mut header = [0u8; 2];
+            self.flash
+                .blocking_read(offset, &mut header)
+                .map_err(|_| Error)?;
+            let len = u16::from_le_bytes(header);
+            if len == EMPTY_LENGTH {
+                break;
+            }
+
+            let data_offset = offset + 2;
+            let next = data_offset + len as u32 + 1;
+            if next > end {
+                // corrupt length, force an erase on the next write
+                return Ok((end, latest));
+            }
+
+            if (len as usize) <= buf.len() {
+                let data = &mut buf[..len as usize];
+                let mut stored = [0u8; 1];
+                self.flash
+                    .blocking_read(data_offset, data)
+                    .map_err(|_| Error)?;
+                self.flash
+                    .blocking_read(next - 1, &mut stored)
+                    .map_err(|_| Error)?;
+                if stored[0] == checksum(data) {
+                    latest = Some((data_offset, len as usize));
+                }
+            }
+
+            offset = next;
+        }
+
+        Ok((offset, latest))
+    }
+}
+
+fn slot_offset(slot: Slot) -> u32 {
+    (FLASH_SIZE - slot as usize * ERASE_SIZE) as u32
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0xA5u8, |sum, &b| sum.rotate_left(1) ^ b)
+}
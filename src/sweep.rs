@@ -0,0 +1,157 @@
+use core::fmt::{Display, Error, Write};
+
+use heapless::{String, Vec};
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+pub const MAX_POINTS: usize = 32;
+
+// distances in millimeters, one for every angle of the sweep
+pub type Sweep = Vec<u16, MAX_POINTS>;
+
+// the angles a sweep stops at, from `start` to at most `end` in steps of `step`
+#[derive(Clone, Copy)]
+pub struct Angles {
+    start: u8,
+    step: u8,
+    points: usize,
+}
+
+impl Angles {
+    pub fn new(start: u8, end: u8, step: u8) -> Result<Self> {
+        if step == 0 || end <= start || end > 180 {
+            return Err(Error);
+        }
+        let points = ((end - start) / step) as usize + 1;
+        if points > MAX_POINTS {
+            return Err(Error);
+        }
+
+        Ok(Self {
+            start,
+            step,
+            points,
+        })
+    }
+
+    pub fn start(&self) -> u8 {
+        self.start
+    }
+
+    pub fn len(&self) -> usize {
+        self.points
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points == 0
+    }
+
+    pub fn get(&self, index: usize) -> u8 {
+        self.start + index as u8 * self.step
+    }
+}
+
+// Compares sweeps against a reference sweep of the empty scene.
+pub struct Reference {
+    // readings further apart than this from the reference are a change
+    threshold: u16,
+    sweep: Option<Sweep>,
+}
+
+impl Reference {
+    pub fn new(threshold: u16) -> Self {
+        Self {
+            threshold,
+            sweep: None,
+        }
+    }
+
+    // forgets the reference, the next sweep becomes the new one
+    pub fn reset(&mut self) {
+        self.sweep = None;
+    }
+
+    // returns the indexes of the angles that changed compared to the reference.
+    // the first sweep becomes the reference.
+    pub fn compare(&mut self, sweep: &Sweep) -> Vec<usize, MAX_POINTS> {
+        let mut changed = Vec::new();
+        let Some(reference) = self.sweep.as_ref() else {
+            self.sweep = Some(sweep.clone());
+            return changed;
+        };
+
+        for (index, (&now, &then)) in sweep.iter().zip(reference.iter()).enumerate() {
+            if now.abs_diff(then) > self.threshold {
+                let _ = changed.push(index);
+            }
+        }
+        changed
+    }
+}
+
+// comma separated values, compact enough to fit a whole sweep in one message
+pub fn to_csv<T: Display, const N: usize>(values: impl Iterator<Item = T>) -> String<N> {
+    let mut csv: String<N> = String::new();
+    for (index, value) in values.enumerate() {
+        // values that don't fit are left out as a whole, separator included
+        let len = csv.len();
+        if (index > 0 && csv.push(',').is_err()) || write!(csv, "{}", value).is_err() {
+            csv.truncate(len);
+            break;
+        }
+    }
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn angles_from_start_to_end() {
+        let angles = Angles::new(30, 150, 20).unwrap();
+        assert_eq!(angles.len(), 7);
+        assert_eq!(angles.get(0), 30);
+        assert_eq!(angles.get(6), 150);
+
+        // the end is not always reached
+        let angles = Angles::new(0, 100, 30).unwrap();
+        assert_eq!(angles.len(), 4);
+        assert_eq!(angles.get(3), 90);
+        assert_eq!(Angles::new(0, 180, 6).unwrap().len(), 31);
+    }
+
+    #[test]
+    fn rejects_invalid_angles() {
+        assert!(Angles::new(0, 180, 0).is_err());
+        assert!(Angles::new(90, 90, 10).is_err());
+        assert!(Angles::new(100, 90, 10).is_err());
+        assert!(Angles::new(0, 181, 10).is_err());
+        // too many points
+        assert!(Angles::new(0, 180, 5).is_err());
+    }
+
+    #[test]
+    fn changes_against_the_reference() {
+        let mut reference = Reference::new(100);
+        let empty = Sweep::from_slice(&[1000, 2000, 3000]).unwrap();
+        assert!(reference.compare(&empty).is_empty());
+
+        let person = Sweep::from_slice(&[1000, 1200, 2900]).unwrap();
+        assert_eq!(reference.compare(&person), [1]);
+        // the reference stays the same
+        assert_eq!(reference.compare(&person), [1]);
+
+        reference.reset();
+        assert!(reference.compare(&person).is_empty());
+        assert!(reference.compare(&person).is_empty());
+    }
+
+    #[test]
+    fn csv_stops_before_overflowing() {
+        let csv: String<16> = to_csv([1000u16, 2000, 3000].iter());
+        assert_eq!(csv, "1000,2000,3000");
+        let csv: String<8> = to_csv([1000u16, 2000, 3000].iter());
+        assert_eq!(csv, "1000");
+    }
+}
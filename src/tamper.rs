@@ -0,0 +1,122 @@
+use embassy_time::{Duration, Instant};
+
+use crate::distance::{Status, Unit};
+
+// A covered sensor reports a constant near-zero distance, no echo at all or
+// the 38ms pulse of an echo that never came back, which reads as out of range.
+// A single reading like that is common, only sustained ones are tampering.
+pub struct TamperDetector {
+    // readings closer than this are implausible
+    min_distance: f64,
+    // how long readings have to be implausible
+    sustain: Duration,
+    blocked_since: Option<Instant>,
+    tampered: bool,
+}
+
+impl TamperDetector {
+    pub fn new(min_distance: f64, sustain: Duration) -> Self {
+        Self {
+            min_distance,
+            sustain,
+            blocked_since: None,
+            tampered: false,
+        }
+    }
+
+    // whether the reading has to be ignored
+    pub fn is_blocked(&self, unit: &Unit) -> bool {
+        match unit.status {
+            Status::TooClose | Status::NoEcho => true,
+            // nothing within range is normal, unless it goes on for too long
+            Status::OutOfRange => self.tampered,
+            Status::Valid => unit.millimeters < self.min_distance,
+        }
+    }
+
+    fn is_suspicious(&self, unit: &Unit) -> bool {
+        unit.status == Status::OutOfRange || self.is_blocked(unit)
+    }
+
+    // returns the new tamper state when it changes
+    pub fn update(&mut self, now: Instant, unit: &Unit) -> Option<bool> {
+        let tampered = if self.is_suspicious(unit) {
+            let since = *self.blocked_since.get_or_insert(now);
+            now.duration_since(since) >= self.sustain
+        } else {
+            self.blocked_since = None;
+            false
+        };
+
+        if tampered == self.tampered {
+            return None;
+        }
+        self.tampered = tampered;
+        Some(tampered)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit(millimeters: f64, status: Status) -> Unit {
+        Unit {
+            millimeters,
+            centimeters: millimeters / 10.0,
+            decimeters: millimeters / 100.0,
+            meters: millimeters / 1000.0,
+            echo: Duration::from_ticks(0),
+            status,
+        }
+    }
+
+    fn detector() -> TamperDetector {
+        TamperDetector::new(50.0, Duration::from_secs(5))
+    }
+
+    #[test]
+    fn readings_closer_than_the_minimum_are_blocked() {
+        let detector = detector();
+        assert!(detector.is_blocked(&unit(49.9, Status::Valid)));
+        assert!(!detector.is_blocked(&unit(50.0, Status::Valid)));
+        assert!(detector.is_blocked(&unit(0.0, Status::TooClose)));
+        assert!(detector.is_blocked(&unit(4000.0, Status::NoEcho)));
+    }
+
+    #[test]
+    fn sustained_close_readings_are_tampering() {
+        let mut detector = detector();
+        let close = unit(30.0, Status::Valid);
+        assert_eq!(detector.update(Instant::from_secs(0), &close), None);
+        assert_eq!(detector.update(Instant::from_secs(4), &close), None);
+        assert_eq!(detector.update(Instant::from_secs(5), &close), Some(true));
+
+        let clear = unit(1200.0, Status::Valid);
+        assert_eq!(detector.update(Instant::from_secs(6), &clear), Some(false));
+        // starts over after a plausible reading
+        assert_eq!(detector.update(Instant::from_secs(7), &close), None);
+        assert_eq!(detector.update(Instant::from_secs(11), &close), None);
+    }
+
+    #[test]
+    fn sustained_out_of_range_readings_are_tampering() {
+        let mut detector = detector();
+        let out_of_range = unit(4000.0, Status::OutOfRange);
+        // a single one is passed on
+        assert_eq!(detector.update(Instant::from_secs(0), &out_of_range), None);
+        assert!(!detector.is_blocked(&out_of_range));
+
+        assert_eq!(
+            detector.update(Instant::from_secs(5), &out_of_range),
+            Some(true)
+        );
+        assert!(detector.is_blocked(&out_of_range));
+
+        assert_eq!(
+            detector.update(Instant::from_secs(6), &unit(800.0, Status::Valid)),
+            Some(false)
+        );
+        assert!(!detector.is_blocked(&out_of_range));
+    }
+}
@@ -0,0 +1,181 @@
+use core::f64::consts::PI;
+use core::fmt::Error;
+use core::str::FromStr;
+
+use heapless::Vec;
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+pub const MAX_LOOKUP_POINTS: usize = 16;
+
+// 1 litre is 1 000 000 cubic millimeters
+const MM3_PER_LITRE: f64 = 1_000_000.0;
+
+// built once at startup, the size of the lookup table doesn't matter
+#[allow(clippy::large_enum_variant)]
+pub enum Geometry {
+    VerticalCylinder { diameter: f64 },
+    HorizontalCylinder { diameter: f64, length: f64 },
+    Rectangular { width: f64, length: f64 },
+    // (level in mm, litres) pairs sorted by level
+    Lookup(Vec<(f64, f64), MAX_LOOKUP_POINTS>),
+}
+
+impl Geometry {
+    pub fn new(shape: &str, diameter: f64, width: f64, length: f64, lookup: &str) -> Result<Self> {
+        match shape {
+            "vertical-cylinder" => Ok(Geometry::VerticalCylinder { diameter }),
+            "horizontal-cylinder" => Ok(Geometry::HorizontalCylinder { diameter, length }),
+            "rectangular" => Ok(Geometry::Rectangular { width, length }),
+            "lookup" => Ok(Geometry::Lookup(parse_lookup(lookup)?)),
+            _ => Err(Error),
+        }
+    }
+
+    // volume in litres when the liquid is `level` mm deep
+    fn litres(&self, level: f64) -> f64 {
+        let volume = match self {
+            Geometry::VerticalCylinder { diameter } => {
+                let radius = diameter / 2.0;
+                PI * radius * radius * level
+            }
+            Geometry::HorizontalCylinder { diameter, length } => {
+                let radius = diameter / 2.0;
+                let level = level.clamp(0.0, *diameter);
+                // area of the circular segment below the liquid surface
+                let area = radius * radius * libm::acos((radius - level) / radius)
+                    - (radius - level) * libm::sqrt(2.0 * radius * level - level * level);
+                area * length
+            }
+            Geometry::Rectangular { width, length } => width * length * level,
+            Geometry::Lookup(points) => return interpolate(points, level),
+        };
+
+        volume / MM3_PER_LITRE
+    }
+}
+
+pub struct Tank {
+    // distance from the sensor to the bottom of the tank
+    mount_height: f64,
+    // sensor reading when the tank is empty
+    empty_distance: f64,
+    // sensor reading when the tank is full
+    full_distance: f64,
+    geometry: Geometry,
+}
+
+pub struct Level {
+    pub millimeters: f64,
+    pub percentage: f64,
+    pub litres: f64,
+}
+
+impl Tank {
+    pub fn new(
+        mount_height: f64,
+        empty_distance: f64,
+        full_distance: f64,
+        geometry: Geometry,
+    ) -> Result<Self> {
+        if full_distance >= empty_distance || empty_distance > mount_height {
+            return Err(Error);
+        }
+
+        Ok(Self {
+            mount_height,
+            empty_distance,
+            full_distance,
+            geometry,
+        })
+    }
+
+    pub fn level(&self, distance: f64) -> Level {
+        // readings outside the empty/full range are noise
+        // or the sensor picking up the tank walls
+        let distance = distance.clamp(self.full_distance, self.empty_distance);
+        let millimeters = self.mount_height - distance;
+        let percentage =
+            (self.empty_distance - distance) / (self.empty_distance - self.full_distance) * 100.0;
+
+        Level {
+            millimeters,
+            percentage,
+            litres: self.geometry.litres(millimeters),
+        }
+    }
+}
+
+fn parse_lookup(lookup: &str) -> Result<Vec<(f64, f64), MAX_LOOKUP_POINTS>> {
+    let mut points: Vec<(f64, f64), MAX_LOOKUP_POINTS> = Vec::new();
+    for pair in lookup.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (level, litres) = pair.split_once(':').ok_or(Error)?;
+        let level = f64::from_str(level.trim()).map_err(|_| Error)?;
+        let litres = f64::from_str(litres.trim()).map_err(|_| Error)?;
+        if let Some(&(previous, _)) = points.last() {
+            if level <= previous {
+                return Err(Error);
+            }
+        }
+        points.push((level, litres)).map_err(|_| Error)?;
+    }
+
+    if points.len() < 2 {
+        return Err(Error);
+    }
+
+    Ok(points)
+}
+
+fn interpolate(points: &[(f64, f64)], level: f64) -> f64 {
+    let (first_level, first_litres) = points[0];
+    if level <= first_level {
+        return first_litres;
+    }
+
+    for window in points.windows(2) {
+        let (low_level, low_litres) = window[0];
+        let (high_level, high_litres) = window[1];
+        if level <= high_level {
+            return low_litres
+                + (level - low_level) / (high_level - low_level) * (high_litres - low_litres);
+        }
+    }
+
+    points[points.len() - 1].1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn level_between_the_empty_and_full_readings() {
+        let geometry = Geometry::new("rectangular", 0.0, 1000.0, 1000.0, "").unwrap();
+        let tank = Tank::new(1200.0, 1150.0, 250.0, geometry).unwrap();
+        let level = tank.level(700.0);
+        assert_eq!(level.millimeters, 500.0);
+        assert_eq!(level.percentage, 50.0);
+        assert_eq!(level.litres, 500.0);
+        // closer than the full reading is noise
+        assert_eq!(tank.level(100.0).percentage, 100.0);
+    }
+
+    #[test]
+    fn interpolates_the_lookup_table() {
+        let geometry = Geometry::new("lookup", 0.0, 0.0, 0.0, "0:0, 500:180,1000:400").unwrap();
+        assert_eq!(geometry.litres(250.0), 90.0);
+        assert_eq!(geometry.litres(750.0), 290.0);
+        assert_eq!(geometry.litres(2000.0), 400.0);
+    }
+
+    #[test]
+    fn rejects_invalid_settings() {
+        assert!(Geometry::new("sphere", 1000.0, 0.0, 0.0, "").is_err());
+        assert!(Geometry::new("lookup", 0.0, 0.0, 0.0, "0:0").is_err());
+        assert!(Geometry::new("lookup", 0.0, 0.0, 0.0, "500:180,0:0").is_err());
+        assert!(Geometry::new("lookup", 0.0, 0.0, 0.0, "0:0,x:1").is_err());
+        let geometry = Geometry::new("vertical-cylinder", 1000.0, 0.0, 0.0, "").unwrap();
+        assert!(Tank::new(1200.0, 250.0, 1150.0, geometry).is_err());
+    }
+}
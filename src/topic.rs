@@ -0,0 +1,105 @@
+use core::fmt::Error;
+
+use heapless::String;
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+pub const MAX_LEN: usize = 64;
+// longest name passed to `state` or `command`, including `cmd/`
+const MAX_NAME_LEN: usize = 24;
+
+pub type Topic = String<MAX_LEN>;
+
+// Every pico publishes below its own `<prefix>/<device id>/`, for example
+// `pico/28cdc1000001/distance`, and takes commands on `.../cmd/<name>`.
+// Otherwise retained messages from several picos overwrite each other.
+pub struct Topics {
+    base: Topic,
+}
+
+impl Topics {
+    pub fn new(prefix: &str, device_id: &str) -> Result<Self> {
+        let mut base = Topic::new();
+        for part in [prefix.trim_end_matches('/'), "/", device_id, "/"] {
+            base.push_str(part).map_err(|_| Error)?;
+        }
+        if base.len() + MAX_NAME_LEN > MAX_LEN {
+            return Err(Error);
+        }
+
+        Ok(Self { base })
+    }
+
+    // without the trailing slash
+    pub fn base(&self) -> &str {
+        self.base.trim_end_matches('/')
+    }
+
+    pub fn state(&self, name: &str) -> Topic {
+        let mut topic = self.base.clone();
+        // cannot fail, `new` leaves room for the name
+        let _ = topic.push_str(name);
+        topic
+    }
+
+    // commands without a name of their own, see `command::Command`
+    pub fn cmd(&self) -> Topic {
+        self.state("cmd")
+    }
+
+    // where commands sent on `cmd` are acknowledged
+    pub fn ack(&self) -> Topic {
+        self.state("ack")
+    }
+
+    pub fn command(&self, name: &str) -> Topic {
+        let mut topic = self.base.clone();
+        let _ = topic.push_str("cmd/");
+        let _ = topic.push_str(name);
+        topic
+    }
+
+    // the name of the command a message was sent to
+    pub fn command_name<'t>(&self, topic: &'t str) -> Option<&'t str> {
+        topic
+            .strip_prefix(self.base.as_str())
+            .and_then(|topic| topic.strip_prefix("cmd/"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn topics_below_the_device() {
+        let topics = Topics::new("pico/", "28cdc1000001").unwrap();
+        assert_eq!(topics.base(), "pico/28cdc1000001");
+        assert_eq!(
+            topics.state("distance").as_str(),
+            "pico/28cdc1000001/distance"
+        );
+        assert_eq!(topics.cmd().as_str(), "pico/28cdc1000001/cmd");
+        assert_eq!(topics.ack().as_str(), "pico/28cdc1000001/ack");
+        assert_eq!(
+            topics.command("time").as_str(),
+            "pico/28cdc1000001/cmd/time"
+        );
+    }
+
+    #[test]
+    fn command_names_only_below_cmd() {
+        let topics = Topics::new("pico", "28cdc1000001").unwrap();
+        assert_eq!(
+            topics.command_name("pico/28cdc1000001/cmd/siren"),
+            Some("siren")
+        );
+        assert_eq!(topics.command_name("pico/28cdc1000001/cmd"), None);
+        assert_eq!(topics.command_name("pico/28cdc1000002/cmd/siren"), None);
+    }
+
+    #[test]
+    fn prefix_too_long_for_the_names() {
+        assert!(Topics::new(&"p".repeat(40), "28cdc1000001").is_err());
+    }
+}
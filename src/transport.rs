@@ -0,0 +1,225 @@
+use core::cell::RefCell;
+use core::fmt::Error;
+use core::future::{poll_fn, Future};
+use core::pin::pin;
+
+use embassy_net::tcp::{self, TcpSocket};
+use embedded_io_async::{ErrorKind, ErrorType, Read, Write};
+
+#[cfg(feature = "tls")]
+use {
+    crate::config,
+    crate::sntp,
+    embassy_rp::clocks::RoscRng,
+    embassy_time::Instant,
+    embedded_tls::pki::CertVerifier,
+    embedded_tls::{
+        Aes128GcmSha256, Certificate, CryptoProvider, CryptoRngCore, TlsClock, TlsConfig,
+        TlsConnection, TlsContext, TlsError, TlsReader, TlsVerifier, TlsWriter,
+    },
+    log::error,
+    rand_chacha::ChaCha8Rng,
+    rand_core::{RngCore, SeedableRng},
+};
+#[cfg(feature = "tls-client-cert")]
+use {
+    embedded_tls::SignatureScheme,
+    p256::ecdsa::{signature::SignerMut, SigningKey},
+    p256::SecretKey,
+};
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+// The mqtt client reads and writes at the same time through the two halves of the
+// connection, plain tcp or tls on top of it with the `tls` feature.
+#[cfg(not(feature = "tls"))]
+pub type Reader<'a, 'b> = Shared<'a, 'b>;
+#[cfg(not(feature = "tls"))]
+pub type Writer<'a, 'b> = Shared<'a, 'b>;
+#[cfg(feature = "tls")]
+pub type Reader<'a, 'b> = TlsReader<'a, Shared<'a, 'b>, Aes128GcmSha256>;
+#[cfg(feature = "tls")]
+pub type Writer<'a, 'b> = TlsWriter<'a, Shared<'a, 'b>, Aes128GcmSha256>;
+
+// What the two halves are split from, it has to outlive them.
+// Plain tcp has nothing to keep.
+#[cfg(not(feature = "tls"))]
+pub type Connection<'a, 'b> = Option<Shared<'a, 'b>>;
+#[cfg(feature = "tls")]
+pub type Connection<'a, 'b> = Option<TlsConnection<'a, Shared<'a, 'b>, Aes128GcmSha256>>;
+
+// largest certificate the broker may send
+#[cfg(feature = "tls")]
+const CERT_SIZE: usize = 4096;
+
+// tls records can be up to 16kB, the read buffer has to hold a whole one
+#[cfg(feature = "tls")]
+pub struct Buffers {
+    read: [u8; 16640],
+    write: [u8; 4096],
+}
+
+#[cfg(not(feature = "tls"))]
+#[derive(Default)]
+pub struct Buffers;
+
+#[cfg(feature = "tls")]
+impl Default for Buffers {
+    fn default() -> Self {
+        Self {
+            read: [0; 16640],
+            write: [0; 4096],
+        }
+    }
+}
+
+// A socket both halves of the connection use. It is only borrowed while
+// a read or write is polled, so one half can wait while the other one works.
+#[derive(Clone, Copy)]
+pub struct Shared<'a, 'b>(&'a RefCell<TcpSocket<'b>>);
+
+impl ErrorType for Shared<'_, '_> {
+    type Error = ErrorKind;
+}
+
+fn kind(error: tcp::Error) -> ErrorKind {
+    match error {
+        tcp::Error::ConnectionReset => ErrorKind::ConnectionReset,
+    }
+}
+
+// the socket futures keep no state between polls, they can be created anew for every poll
+impl Read for Shared<'_, '_> {
+    async fn read(&mut self, buf: &mut [u8]) -> core::result::Result<usize, ErrorKind> {
+        poll_fn(|cx| {
+            let mut socket = self.0.borrow_mut();
+            let mut read = pin!(socket.read(buf));
+            read.as_mut().poll(cx)
+        })
+        .await
+        .map_err(kind)
+    }
+}
+
+impl Write for Shared<'_, '_> {
+    async fn write(&mut self, buf: &[u8]) -> core::result::Result<usize, ErrorKind> {
+        poll_fn(|cx| {
+            let mut socket = self.0.borrow_mut();
+            let mut write = pin!(socket.write(buf));
+            write.as_mut().poll(cx)
+        })
+        .await
+        .map_err(kind)
+    }
+
+    async fn flush(&mut self) -> core::result::Result<(), ErrorKind> {
+        poll_fn(|cx| {
+            let mut socket = self.0.borrow_mut();
+            let mut flush = pin!(socket.flush());
+            flush.as_mut().poll(cx)
+        })
+        .await
+        .map_err(kind)
+    }
+}
+
+// the validity period of the broker certificate is only checked once the clock has been
+// synced over sntp, before that it is ignored
+#[cfg(feature = "tls")]
+struct SyncedClock;
+
+#[cfg(feature = "tls")]
+impl TlsClock for SyncedClock {
+    fn now() -> Option<u64> {
+        sntp::clock().unix_ms(Instant::now()).map(|ms| ms / 1000)
+    }
+}
+
+// the handshake draws from a seeded rng and checks the broker certificate
+#[cfg(feature = "tls")]
+struct Provider {
+    rng: ChaCha8Rng,
+    verifier: CertVerifier<Aes128GcmSha256, SyncedClock, CERT_SIZE>,
+}
+
+#[cfg(feature = "tls")]
+impl CryptoProvider for Provider {
+    type CipherSuite = Aes128GcmSha256;
+    type Signature = p256::ecdsa::DerSignature;
+
+    fn rng(&mut self) -> impl CryptoRngCore {
+        &mut self.rng
+    }
+
+    fn verifier(
+        &mut self,
+    ) -> core::result::Result<&mut impl TlsVerifier<Aes128GcmSha256>, TlsError> {
+        Ok(&mut self.verifier)
+    }
+
+    // the client key is a sec1 encoded P-256 key
+    #[cfg(feature = "tls-client-cert")]
+    fn signer(
+        &mut self,
+        key_der: &[u8],
+    ) -> core::result::Result<(impl SignerMut<Self::Signature>, SignatureScheme), TlsError> {
+        let key = SecretKey::from_sec1_der(key_der).map_err(|_| TlsError::InvalidPrivateKey)?;
+        Ok((
+            SigningKey::from(&key),
+            SignatureScheme::EcdsaSecp256r1Sha256,
+        ))
+    }
+}
+
+#[cfg(not(feature = "tls"))]
+pub async fn open<'a, 'b>(
+    socket: &'a RefCell<TcpSocket<'b>>,
+    _buffers: &'a mut Buffers,
+    _connection: &'a mut Connection<'a, 'b>,
+    _host: &str,
+) -> Result<(Reader<'a, 'b>, Writer<'a, 'b>)> {
+    Ok((Shared(socket), Shared(socket)))
+}
+
+// the broker has to present a certificate signed by `RP_MQTT_CA_CERT`,
+// valid now if the clock has been synced
+#[cfg(feature = "tls")]
+pub async fn open<'a, 'b>(
+    socket: &'a RefCell<TcpSocket<'b>>,
+    buffers: &'a mut Buffers,
+    connection: &'a mut Connection<'a, 'b>,
+    host: &str,
+) -> Result<(Reader<'a, 'b>, Writer<'a, 'b>)> {
+    let server_name = match config::MQTT_TLS_SERVER_NAME {
+        "" => host,
+        name => name,
+    };
+    let tls_config = TlsConfig::new()
+        .with_server_name(server_name)
+        .with_ca(Certificate::X509(config::MQTT_CA_CERT));
+    #[cfg(feature = "tls-client-cert")]
+    let tls_config = tls_config
+        .with_cert(Certificate::X509(config::MQTT_CLIENT_CERT))
+        .with_priv_key(config::MQTT_CLIENT_KEY);
+
+    let Buffers { read, write } = buffers;
+    // the ring oscillator is too slow to feed the handshake directly
+    let provider = Provider {
+        rng: ChaCha8Rng::seed_from_u64(RoscRng.next_u64()),
+        verifier: CertVerifier::new(),
+    };
+    let tls = connection.insert(TlsConnection::new(Shared(socket), read, write));
+    tls.open(TlsContext::new(&tls_config, provider))
+        .await
+        .map_err(|e| {
+            error!("tls handshake failed: {:?}", e);
+            Error
+        })?;
+
+    Ok(tls.split())
+}
+
+// the mqtt disconnect has been written by then, the broker doesn't wait for a tls close
+pub fn close(socket: &RefCell<TcpSocket<'_>>) {
+    socket.borrow_mut().close();
+}